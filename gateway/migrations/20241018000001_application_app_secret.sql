@@ -0,0 +1,2 @@
+-- Per-application Meta app secret used to verify X-Hub-Signature-256 on webhooks.
+ALTER TABLE application ADD COLUMN IF NOT EXISTS app_secret VARCHAR;
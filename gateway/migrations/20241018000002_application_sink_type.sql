@@ -0,0 +1,4 @@
+-- Delivery backend per application: redis_pubsub, redis_stream or amqp.
+ALTER TABLE application
+    ADD COLUMN IF NOT EXISTS sink_type VARCHAR NOT NULL DEFAULT 'redis_pubsub'
+    CHECK (sink_type IN ('redis_pubsub', 'redis_stream', 'amqp'));
@@ -0,0 +1,19 @@
+-- Durable hand-off between webhook ingestion and delivery to app sinks.
+CREATE TABLE IF NOT EXISTS webhook_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    app_id INT NOT NULL,
+    topic VARCHAR NOT NULL,
+    sink_type VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    trace_id VARCHAR,
+    status VARCHAR NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'delivered', 'dead')),
+    attempts INT NOT NULL DEFAULT 0,
+    last_error TEXT,
+    next_attempt_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    delivered_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS webhook_outbox_pending_idx
+    ON webhook_outbox (next_attempt_at)
+    WHERE status = 'pending';
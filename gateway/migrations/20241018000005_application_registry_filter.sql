@@ -0,0 +1,2 @@
+-- Optional per-application filter applied before an entry is routed to the app.
+ALTER TABLE application_registry ADD COLUMN IF NOT EXISTS filter JSONB;
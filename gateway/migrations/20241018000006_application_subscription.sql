@@ -0,0 +1,8 @@
+-- Event types an application receives, optionally on a topic of their own.
+-- An application without subscriptions receives every event on its topic.
+CREATE TABLE IF NOT EXISTS application_subscription (
+    app_id INTEGER NOT NULL REFERENCES application (id) ON DELETE CASCADE,
+    event_type VARCHAR NOT NULL,
+    topic VARCHAR,
+    PRIMARY KEY (app_id, event_type)
+);
@@ -0,0 +1,13 @@
+-- Events for disabled applications held back under DISABLED_APP_POLICY=park,
+-- moved to webhook_outbox when the application is resumed.
+CREATE TABLE IF NOT EXISTS parked_event (
+    id BIGSERIAL PRIMARY KEY,
+    app_id INT NOT NULL REFERENCES application (id) ON DELETE CASCADE,
+    topic VARCHAR NOT NULL,
+    sink_type VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    trace_id VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS parked_event_app_idx ON parked_event (app_id, id);
@@ -0,0 +1,9 @@
+-- Signed webhook payloads that could not be parsed, kept for inspection.
+CREATE TABLE IF NOT EXISTS webhook_quarantine (
+    id BIGSERIAL PRIMARY KEY,
+    object VARCHAR,
+    payload TEXT NOT NULL,
+    error TEXT NOT NULL,
+    trace_id VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,6 @@
+-- Page access tokens are envelope encrypted: `token` holds the AES-256-GCM
+-- ciphertext and `token_dek` its data key, wrapped with the master key named by
+-- `token_key_id`. Rows with a null `token_key_id` are still plaintext until
+-- POST /merchants/tokens/rotate encrypts them.
+ALTER TABLE merchant_channel ADD COLUMN IF NOT EXISTS token_key_id VARCHAR;
+ALTER TABLE merchant_channel ADD COLUMN IF NOT EXISTS token_dek TEXT;
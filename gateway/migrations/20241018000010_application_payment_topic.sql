@@ -0,0 +1,3 @@
+-- Topic receiving normalized P2M payment events; null keeps payment changes
+-- on the regular topics.
+ALTER TABLE application ADD COLUMN IF NOT EXISTS payment_topic VARCHAR;
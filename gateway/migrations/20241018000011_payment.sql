@@ -0,0 +1,41 @@
+-- Latest known state of every P2M payment, upserted on each change event.
+CREATE TABLE IF NOT EXISTS payment (
+    payment_id VARCHAR PRIMARY KEY,
+    page_id VARCHAR NOT NULL,
+    buyer_id VARCHAR,
+    order_id VARCHAR,
+    invoice_id VARCHAR,
+    payment_method VARCHAR,
+    amount NUMERIC,
+    currency VARCHAR,
+    status VARCHAR NOT NULL,
+    is_duplicate BOOLEAN NOT NULL DEFAULT FALSE,
+    metadata JSONB,
+    last_event_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS payment_page_idx ON payment (page_id, created_at);
+CREATE INDEX IF NOT EXISTS payment_buyer_idx ON payment (buyer_id, created_at);
+CREATE INDEX IF NOT EXISTS payment_order_idx ON payment (order_id);
+
+-- Every payment and invoice change seen. Invoice changes without a payment
+-- only appear here.
+CREATE TABLE IF NOT EXISTS payment_status_history (
+    id BIGSERIAL PRIMARY KEY,
+    payment_id VARCHAR REFERENCES payment (payment_id) ON DELETE CASCADE,
+    invoice_id VARCHAR,
+    page_id VARCHAR NOT NULL,
+    event VARCHAR,
+    status VARCHAR NOT NULL,
+    event_at TIMESTAMPTZ NOT NULL,
+    trace_id VARCHAR,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Meta redeliveries are recorded once even with dedupe disabled.
+CREATE UNIQUE INDEX IF NOT EXISTS payment_status_history_event_idx ON payment_status_history (
+    COALESCE(payment_id, ''), COALESCE(invoice_id, ''), COALESCE(event, ''), status, event_at
+);
+CREATE INDEX IF NOT EXISTS payment_status_history_payment_idx ON payment_status_history (payment_id, event_at);
@@ -0,0 +1,15 @@
+-- Bank slip problems flagged by the payment alert rules, at most one per
+-- payment and rule. `published_at` stays null when publishing to the alerts
+-- topic failed.
+CREATE TABLE IF NOT EXISTS payment_alert (
+    id BIGSERIAL PRIMARY KEY,
+    payment_id VARCHAR NOT NULL REFERENCES payment (payment_id) ON DELETE CASCADE,
+    page_id VARCHAR NOT NULL,
+    rule VARCHAR NOT NULL,
+    detail TEXT NOT NULL,
+    event_at TIMESTAMPTZ NOT NULL,
+    trace_id VARCHAR,
+    published_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (payment_id, rule)
+);
@@ -0,0 +1,33 @@
+-- Every accepted webhook entry, one row per channel it was routed for, kept
+-- for replays. Partitioned by day so old days can be dropped as a whole.
+CREATE TABLE IF NOT EXISTS webhook_archive (
+    id BIGSERIAL,
+    received_at TIMESTAMPTZ NOT NULL,
+    object VARCHAR NOT NULL,
+    page_id VARCHAR NOT NULL,
+    event_types VARCHAR[] NOT NULL DEFAULT '{}',
+    request_id VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    PRIMARY KEY (id, received_at)
+) PARTITION BY RANGE (received_at);
+
+CREATE INDEX IF NOT EXISTS webhook_archive_page_idx ON webhook_archive (page_id, received_at);
+
+-- Catches rows for a day whose partition is missing.
+CREATE TABLE IF NOT EXISTS webhook_archive_default PARTITION OF webhook_archive DEFAULT;
+
+-- Creates the partition holding `day`; safe to call concurrently. When rows
+-- for the day already landed in the default partition they stay there.
+CREATE OR REPLACE FUNCTION webhook_archive_ensure_partition(day DATE) RETURNS VOID AS $$
+BEGIN
+    EXECUTE format(
+        'CREATE TABLE IF NOT EXISTS %I PARTITION OF webhook_archive FOR VALUES FROM (%L) TO (%L)',
+        'webhook_archive_' || to_char(day, 'YYYYMMDD'),
+        day::timestamptz,
+        (day + 1)::timestamptz
+    );
+EXCEPTION
+    WHEN duplicate_table OR unique_violation OR check_violation THEN
+        NULL;
+END;
+$$ LANGUAGE plpgsql;
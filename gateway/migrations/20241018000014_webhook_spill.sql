@@ -0,0 +1,9 @@
+-- Verified webhook bodies that did not fit the in-process ingest queue, or
+-- whose processing failed, waiting to be picked up again by the workers.
+CREATE TABLE IF NOT EXISTS webhook_spill (
+    id BIGSERIAL PRIMARY KEY,
+    payload TEXT NOT NULL,
+    trace_id VARCHAR NOT NULL,
+    attempts INT NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
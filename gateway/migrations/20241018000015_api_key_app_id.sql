@@ -0,0 +1,3 @@
+-- Application an API key acts for. `/send` only lets a key send as pages its
+-- application is registered to; keys without one can only send with `admin`.
+ALTER TABLE api_key ADD COLUMN IF NOT EXISTS app_id VARCHAR;
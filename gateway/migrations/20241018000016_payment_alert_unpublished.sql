@@ -0,0 +1,4 @@
+-- Lets the alert sweeper find alerts that were never published.
+CREATE INDEX IF NOT EXISTS payment_alert_unpublished_idx
+    ON payment_alert (id)
+    WHERE published_at IS NULL;
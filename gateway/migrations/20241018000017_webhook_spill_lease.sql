@@ -0,0 +1,7 @@
+-- Spilled webhooks are leased instead of deleted when claimed, and only
+-- removed once processed. `locked_until` is also when a failed webhook is
+-- due for its next attempt.
+ALTER TABLE webhook_spill ADD COLUMN IF NOT EXISTS locked_until TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX IF NOT EXISTS webhook_spill_due_idx
+    ON webhook_spill (locked_until, id);
@@ -0,0 +1,4 @@
+-- Lets the outbox purge find delivered messages past the retention.
+CREATE INDEX IF NOT EXISTS webhook_outbox_delivered_idx
+    ON webhook_outbox (delivered_at)
+    WHERE status = 'delivered';
@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use emit::{__emit_get_event_data, emit, info, warn};
+use tokio::task::JoinHandle;
+
+use crate::{
+    database::Database,
+    models::{
+        payment::PaymentRecord,
+        payment_alert::{AlertRule, PaymentAlert, PublishedPaymentAlert},
+    },
+    sinks::{MessageSinks, SinkKind},
+    utils::config::env_or,
+};
+
+/// Rules run over incoming bank slip payments. Hits are stored with the
+/// payment in `payment_alert`, once per payment and rule, and new ones are
+/// published to the alerts topic. Alerts whose publish failed or never ran are
+/// picked up again by the sweeper.
+#[derive(Clone)]
+pub struct PaymentAlerts {
+    database: Database,
+    sinks: MessageSinks,
+    rules: Vec<AlertRule>,
+    topic: String,
+    sink: SinkKind,
+    sweep_interval: Duration,
+    sweep_batch_size: i64,
+}
+
+impl PaymentAlerts {
+    pub fn from_env(database: Database, sinks: MessageSinks) -> Self {
+        let all = AlertRule::ALL.map(|rule| rule.as_str()).join(",");
+        let rules = env_or("PAYMENT_ALERT_RULES", all)
+            .split(',')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+            .filter_map(|rule| match rule.parse::<AlertRule>() {
+                Ok(rule) => Some(rule),
+                Err(err) => {
+                    warn!("Ignoring payment alert rule, error: {}", error: err.to_string());
+                    None
+                }
+            })
+            .collect();
+
+        PaymentAlerts {
+            database,
+            sinks,
+            rules,
+            topic: env_or("PAYMENT_ALERTS_TOPIC", "femto.payment-alerts".to_string()),
+            sink: env_or("PAYMENT_ALERTS_SINK", SinkKind::RedisPubsub),
+            sweep_interval: Duration::from_secs(env_or("PAYMENT_ALERT_SWEEP_INTERVAL_SECS", 60)),
+            sweep_batch_size: env_or("PAYMENT_ALERT_SWEEP_BATCH_SIZE", 100),
+        }
+    }
+
+    pub fn evaluate(&self, record: &PaymentRecord) -> Vec<PaymentAlert> {
+        let event = &record.event;
+        let Some(payment_id) = &event.payment_id else {
+            return Vec::new();
+        };
+        let validation = event.validation.as_ref();
+
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let detail = match rule {
+                    AlertRule::DuplicateSlip => event.is_duplicate.then(|| "bank slip was already submitted".to_string()),
+                    AlertRule::AmountMismatch => validation.and_then(|validation| {
+                        let currency_differs = matches!(
+                            (&event.currency, &validation.currency),
+                            (Some(paid), Some(validated)) if paid != validated
+                        );
+                        (validation.amount_matches == Some(false) || currency_differs).then(|| {
+                            format!(
+                                "payment amount {} {} differs from validated amount {} {}",
+                                display(&event.amount),
+                                display(&event.currency),
+                                display(&validation.amount),
+                                display(&validation.currency),
+                            )
+                        })
+                    }),
+                    AlertRule::SellerAccountMismatch => validation
+                        .filter(|validation| validation.matches_seller_account == Some(false))
+                        .map(|_| "bank slip receiver does not match the seller account".to_string()),
+                    AlertRule::SellerNotOnboarded => validation
+                        .filter(|validation| validation.seller_onboarded == Some(false))
+                        .map(|_| "seller is not onboarded for bank slip validation".to_string()),
+                }?;
+
+                Some(PaymentAlert {
+                    rule: *rule,
+                    payment_id: payment_id.clone(),
+                    page_id: event.page_id.clone(),
+                    buyer_id: event.buyer_id.clone(),
+                    order_id: event.order_id.clone(),
+                    detail,
+                    event_at: record.event_at,
+                    trace_id: record.trace_id.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Publishes newly stored alerts in the background. Alerts that fail to
+    /// publish keep a null `published_at` until the sweeper retries them.
+    pub fn spawn_publish(&self, alerts: Vec<(i64, PaymentAlert)>) {
+        if alerts.is_empty() {
+            return;
+        }
+
+        let this = self.clone();
+        tokio::spawn(async move {
+            this.publish(alerts).await;
+        });
+    }
+
+    /// Republishes, every sweep interval, the alerts left with a null
+    /// `published_at` for longer than that interval. Delivery is at least
+    /// once; consumers dedupe on `alert_id`.
+    pub fn spawn_sweeper(&self) -> JoinHandle<()> {
+        let this = self.clone();
+        tokio::spawn(async move { this.sweep().await })
+    }
+
+    async fn sweep(&self) {
+        loop {
+            tokio::time::sleep(self.sweep_interval).await;
+
+            loop {
+                let alerts = match self
+                    .database
+                    .unpublished_payment_alerts(self.sweep_interval.as_secs_f64(), self.sweep_batch_size)
+                    .await
+                {
+                    Ok(alerts) => alerts,
+                    Err(err) => {
+                        warn!("Failed to load unpublished payment alerts, error: {}", error: err.to_string());
+                        break;
+                    }
+                };
+                let swept = alerts.len();
+                if swept == 0 {
+                    break;
+                }
+
+                info!("Republishing {} unpublished payment alerts", count: swept);
+                let published = self.publish(alerts).await;
+                // Stop at a full batch that made no progress, the sink is likely down.
+                if published == 0 || (swept as i64) < self.sweep_batch_size {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Publishes `alerts` and marks the delivered ones, returning how many.
+    async fn publish(&self, alerts: Vec<(i64, PaymentAlert)>) -> usize {
+        let mut published = Vec::with_capacity(alerts.len());
+        for (alert_id, alert) in &alerts {
+            let payload = match serde_json::to_string(&PublishedPaymentAlert { alert_id: *alert_id, alert }) {
+                Ok(payload) => payload,
+                Err(err) => {
+                    warn!("Failed to serialize payment alert {}, error: {}", alert_id: alert_id, error: err.to_string());
+                    continue;
+                }
+            };
+
+            match self.sinks.publish(self.sink, &self.topic, &payload).await {
+                Ok(()) => {
+                    info!("Payment alert {} published, rule: {}, payment ID {}",
+                        alert_id: alert_id,
+                        rule: alert.rule.as_str(),
+                        payment_id: alert.payment_id);
+                    published.push(*alert_id);
+                }
+                Err(err) => warn!("Failed to publish payment alert {}, error: {}", alert_id: alert_id, error: err.to_string()),
+            }
+        }
+
+        if let Err(err) = self.database.mark_payment_alerts_published(&published).await {
+            warn!("Failed to mark payment alerts published, error: {}", error: err.to_string());
+            return 0;
+        }
+        published.len()
+    }
+}
+
+fn display<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(ToString::to_string).unwrap_or_else(|| "?".to_string())
+}
@@ -0,0 +1,198 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use emit::{__emit_get_event_data, emit, error, info, warn};
+use serde_json::value::RawValue;
+
+use crate::{
+    database::Database,
+    errors::AppError,
+    models::{
+        archive::{ArchivedEntry, ArchivedEntrySummary, ReplayRequest, ReplayResponse, ReplayTarget},
+        merchant_channel::ChannelType,
+        messenger_webhook::{RawWrappedMessage, WebhookEntry, WrappedMessage},
+        outbox::NewOutboxMessage,
+        whatsapp_webhook::{WhatsAppEntry, WrappedWhatsAppMessage},
+    },
+    outbox::Outbox,
+    routing::ForwardMode,
+    utils::config::env_or,
+};
+
+/// Re-publishes archived webhook entries to an application's topic through
+/// the outbox, at most `rate` entries per second and one replay per
+/// application at a time.
+#[derive(Clone)]
+pub struct Replayer {
+    database: Database,
+    outbox: Outbox,
+    forward_mode: ForwardMode,
+    rate: usize,
+    running: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Replayer {
+    pub fn from_env(database: Database, outbox: Outbox, forward_mode: ForwardMode) -> Self {
+        Replayer {
+            database,
+            outbox,
+            forward_mode,
+            rate: env_or("REPLAY_RATE_PER_SEC", 100_usize).max(1),
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Starts replaying the entries matching `req` in the background, or only
+    /// lists them on a dry run. Raw entries are replayed whole, so filtering
+    /// by `event_types` is refused in raw forward mode.
+    pub async fn start(&self, req: ReplayRequest) -> Result<ReplayResponse, AppError> {
+        if req.event_types.is_some() && self.forward_mode == ForwardMode::Raw {
+            info!("Refusing replay for application {} filtered by event types in raw mode", app_id: req.app_id);
+            return Err(AppError::bad_request());
+        }
+        let target = self
+            .database
+            .get_replay_target(&req.app_id)
+            .await?
+            .ok_or_else(AppError::not_found)?;
+        let entries = self.database.find_archived_entries(&req).await?;
+        let matched = entries.len() as u64;
+
+        if req.dry_run {
+            return Ok(ReplayResponse {
+                matched,
+                dry_run: true,
+                entries: Some(entries.iter().map(ArchivedEntrySummary::from).collect()),
+            });
+        }
+        if !target.enabled {
+            info!("Application {} is disabled, refusing replay", app_id: req.app_id);
+            return Err(AppError::conflict());
+        }
+        if !self.claim(&req.app_id) {
+            info!("Replay for application {} already running", app_id: req.app_id);
+            return Err(AppError::conflict());
+        }
+
+        info!("Replaying {} archived entries to application {}", matched: matched, app_id: req.app_id);
+        let replayer = self.clone();
+        tokio::spawn(async move {
+            replayer.replay(&req, &target, entries).await;
+            replayer.release(&req.app_id);
+        });
+
+        Ok(ReplayResponse {
+            matched,
+            dry_run: false,
+            entries: None,
+        })
+    }
+
+    fn claim(&self, app_id: &str) -> bool {
+        match self.running.lock() {
+            Ok(mut running) => running.insert(app_id.to_string()),
+            Err(poisoned) => poisoned.into_inner().insert(app_id.to_string()),
+        }
+    }
+
+    fn release(&self, app_id: &str) {
+        match self.running.lock() {
+            Ok(mut running) => running.remove(app_id),
+            Err(poisoned) => poisoned.into_inner().remove(app_id),
+        };
+    }
+
+    async fn replay(&self, req: &ReplayRequest, target: &ReplayTarget, entries: Vec<ArchivedEntry>) {
+        let event_types = req.event_types.as_deref();
+        let mut replayed = 0;
+        for (index, chunk) in entries.chunks(self.rate).enumerate() {
+            if index > 0 {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+
+            let messages = chunk
+                .iter()
+                .filter_map(|entry| match self.payload(entry, event_types) {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        warn!("Skipping archived entry {}, error: {}", archive_id: entry.id, error: err.to_string());
+                        None
+                    }
+                })
+                .map(|(entry, payload)| {
+                    NewOutboxMessage::new(target.id, target.topic.clone(), target.sink_type.clone(), payload, Some(entry.request_id.clone()))
+                })
+                .collect::<Vec<NewOutboxMessage>>();
+            let count = messages.len();
+
+            if let Err(err) = self.outbox.enqueue(messages, Vec::new()).await {
+                error!("Replay for application {} stopped after {} entries, error: {}",
+                    app_id: req.app_id,
+                    replayed: replayed,
+                    error: err.to_string());
+                return;
+            }
+            replayed += count;
+        }
+
+        info!("Replay for application {} finished, {} entries replayed", app_id: req.app_id, replayed: replayed);
+    }
+
+    /// Wraps `entry` the way it was published when received, keeping only
+    /// `event_types` when given. `None` when no event is left.
+    fn payload<'a>(&self, entry: &'a ArchivedEntry, event_types: Option<&[String]>) -> Result<Option<(&'a ArchivedEntry, String)>, AppError> {
+        let to_json = |err: serde_json::Error| AppError::InternalServerError(err.to_string());
+
+        let payload = match (ChannelType::from_object(&entry.object), &self.forward_mode) {
+            (None, _) if event_types.is_some() => {
+                return Err(AppError::InternalServerError(format!("cannot filter {} entries by event type", entry.object)));
+            }
+            (None, _) | (_, ForwardMode::Raw) => {
+                let page_entry = serde_json::from_str::<Box<RawValue>>(&entry.payload).map_err(to_json)?;
+                let message = RawWrappedMessage {
+                    trace_id: &entry.request_id,
+                    object: &entry.object,
+                    page_entry: &page_entry,
+                };
+                serde_json::to_string(&message).map_err(to_json)?
+            }
+            (Some(ChannelType::WhatsApp), ForwardMode::Typed) => {
+                let mut whatsapp_entry = serde_json::from_str::<WhatsAppEntry>(&entry.payload)
+                    .map_err(to_json)?
+                    .for_routing_id(&entry.page_id);
+                if let Some(event_types) = event_types {
+                    whatsapp_entry.retain_event_types(event_types);
+                }
+                if whatsapp_entry.is_empty() {
+                    return Ok(None);
+                }
+                let message = WrappedWhatsAppMessage {
+                    trace_id: entry.request_id.clone(),
+                    object: entry.object.clone(),
+                    entry: whatsapp_entry,
+                };
+                serde_json::to_string(&message).map_err(to_json)?
+            }
+            (Some(_), ForwardMode::Typed) => {
+                let mut page_entry = serde_json::from_str::<WebhookEntry>(&entry.payload).map_err(to_json)?;
+                if let Some(event_types) = event_types {
+                    page_entry.retain_event_types(event_types);
+                }
+                if page_entry.is_empty() {
+                    return Ok(None);
+                }
+                let message = WrappedMessage {
+                    trace_id: entry.request_id.clone(),
+                    object: entry.object.clone(),
+                    page_entry,
+                };
+                serde_json::to_string(&message).map_err(to_json)?
+            }
+        };
+
+        Ok(Some((entry, payload)))
+    }
+}
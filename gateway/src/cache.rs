@@ -1,29 +1,86 @@
-use std::env;
-use redis::{Commands};
-use crate::errors::AppError;
+use std::{env, sync::Arc, time::Duration};
+use redis::aio::{ConnectionManager, ConnectionManagerConfig, PubSub};
+use tokio::sync::OnceCell;
+use crate::{errors::AppError, utils::config::env_or};
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct CacheService {
     redis: redis::Client,
+    /// Bounds both connecting and each command, so a stalled Redis fails the
+    /// request with a timeout instead of hanging it.
+    timeout: Duration,
+    /// One multiplexed connection shared by every caller, opened on first use
+    /// and re-established by the manager when Redis drops it.
+    manager: Arc<OnceCell<ConnectionManager>>,
 }
 
 impl CacheService {
-    pub async fn init() -> Self {
-        let redis_url = env::var("REDIS_URL").expect("env::REDIS_URL is missing");
-        let client = redis::Client::open(redis_url).expect("Error to init redis client");
+    pub async fn init() -> Result<Self, AppError> {
+        let redis_url = env::var("REDIS_URL")
+            .map_err(|_| AppError::InternalServerError("env::REDIS_URL is missing".to_string()))?;
+        let client = redis::Client::open(redis_url)?;
 
-        CacheService { redis: client }
+        Ok(CacheService {
+            redis: client,
+            timeout: Duration::from_millis(env_or("REDIS_TIMEOUT_MS", 2_000)),
+            manager: Arc::new(OnceCell::new()),
+        })
+    }
+
+    pub async fn connection(&self) -> Result<ConnectionManager, AppError> {
+        let manager = self
+            .manager
+            .get_or_try_init(|| {
+                let config = ConnectionManagerConfig::new()
+                    .set_connection_timeout(self.timeout)
+                    .set_response_timeout(self.timeout)
+                    .set_number_of_retries(1);
+                self.redis.get_connection_manager_with_config(config)
+            })
+            .await?;
+        Ok(manager.clone())
     }
 
     pub async fn ping(&self) -> Result<String, AppError> {
-        let mut con = self.redis.get_multiplexed_async_connection().await?;
+        let mut con = self.connection().await?;
         let pong: String = redis::cmd("PING").query_async(&mut con).await?;
         Ok(pong)
     }
 
+    /// `SET key 1 NX EX ttl` for every key in one pipeline. Returns, per key,
+    /// `true` when it did not exist yet.
+    pub async fn set_if_absent_many(&self, keys: &[String], ttl_secs: u64) -> Result<Vec<bool>, AppError> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut con = self.connection().await?;
+        let mut pipe = redis::pipe();
+        for key in keys {
+            pipe.cmd("SET").arg(key).arg(1).arg("NX").arg("EX").arg(ttl_secs);
+        }
+        let res: Vec<Option<String>> = pipe.query_async(&mut con).await?;
+        Ok(res.into_iter().map(|set| set.is_some()).collect())
+    }
+
+    pub async fn delete(&self, keys: &[String]) -> Result<(), AppError> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let mut con = self.connection().await?;
+        let _: () = redis::cmd("DEL").arg(keys).query_async(&mut con).await?;
+        Ok(())
+    }
+
     pub async fn publish(&self, topic: String, value: String) -> Result<(), AppError> {
-        let mut conn = self.redis.clone().get_connection()?;
-        let _:() = conn.publish(topic, value)?;
+        let mut con = self.connection().await?;
+        let _: () = redis::cmd("PUBLISH").arg(topic).arg(value).query_async(&mut con).await?;
         Ok(())
     }
+
+    /// Opens a dedicated connection subscribed to `channel`.
+    pub async fn subscribe(&self, channel: &str) -> Result<PubSub, AppError> {
+        let mut pubsub = self.redis.get_async_pubsub().await?;
+        pubsub.subscribe(channel).await?;
+        Ok(pubsub)
+    }
 }
@@ -1,83 +1,290 @@
 use crate::{
-    errors::AppError, models::application::Application, models::merchant_channel::MerchantChannel,
+    errors::AppError,
+    models::application::{Application, CreateApplicationRequest, PatchApplicationRequest},
+    models::application_registry::{ApplicationRegistry, RegistryFilter},
+    models::merchant_channel::{
+        ChannelType, CreateMerchantChannelRequest, EncryptedToken, MerchantChannel, PatchMerchantChannelRequest,
+    },
 };
+use chrono::NaiveDate;
 use moka::future::Cache;
-use sqlx::{postgres::PgPoolOptions, PgPool};
-use std::{env, time::Duration};
+use sqlx::{postgres::PgPoolOptions, types::Json, PgConnection, PgPool};
+use std::{collections::HashMap, env, time::Duration};
 
 use emit::{__emit_get_event_data, emit, info};
-use crate::models::merchant_config::MerchantConfig;
+use crate::models::api_key::ApiKey;
+use crate::models::archive::{ArchivedEntry, NewArchivedEntry, ReplayRequest, ReplayTarget};
+use crate::models::ingest::SpilledWebhook;
+use crate::models::merchant_config::{MerchantConfigRow, MerchantLookup};
+use crate::models::outbox::{NewOutboxMessage, OutboxMessage};
+use crate::models::payment::{Payment, PaymentRecord, PaymentStatusChange, SearchPayments};
+use crate::models::payment_alert::{PaymentAlert, PaymentAlertRow};
+use crate::models::subscription::Subscription;
+use crate::utils::config::env_or;
 
 #[derive(Clone, Debug)]
 pub struct Database {
     pub client: PgPool,
     eligibility: Cache<String, bool>,
+    merchant_configs: Cache<String, MerchantLookup>,
+    /// Days whose `webhook_archive` partition is known to exist.
+    archive_partitions: Cache<NaiveDate, ()>,
 }
 
 impl Database {
-    pub async fn init() -> Self {
-        let database_url = env::var("DATABASE_URL").expect("env::DATABASE_URL is missing");
+    pub async fn init() -> Result<Self, AppError> {
+        let database_url = env::var("DATABASE_URL")
+            .map_err(|_| AppError::InternalServerError("env::DATABASE_URL is missing".to_string()))?;
         let client = PgPoolOptions::new()
             .max_connections(20)
+            .acquire_timeout(Duration::from_millis(env_or("DATABASE_ACQUIRE_TIMEOUT_MS", 5_000)))
             .connect(&database_url)
-            .await
-            .expect("Unable to connect to database");
+            .await?;
         let eligibility: Cache<String, bool> = Cache::builder()
             .max_capacity(10_000) // Max 10,000 entries
             .time_to_live(Duration::from_secs(30 * 60)) // Time to live (TTL): 30 minutes
             .time_to_idle(Duration::from_secs(5 * 60)) // Time to idle (TTI):  5 minutes
             .build();
+        let merchant_configs: Cache<String, MerchantLookup> = Cache::builder()
+            .max_capacity(10_000)
+            .time_to_live(Duration::from_secs(30 * 60))
+            .time_to_idle(Duration::from_secs(5 * 60))
+            .build();
+        let archive_partitions: Cache<NaiveDate, ()> = Cache::builder()
+            .max_capacity(64)
+            .build();
 
-        Database {
+        Ok(Database {
             client,
             eligibility,
-        }
+            merchant_configs,
+            archive_partitions,
+        })
     }
 
     pub async fn get_now(&self) -> Result<String, AppError> {
         let res: (String,) = sqlx::query_as("SELECT NOW()::VARCHAR;")
-            .bind(150_i64)
             .fetch_one(&self.client)
-            .await
-            .expect("");
+            .await?;
         let date_now = res.0;
 
         Ok(date_now)
     }
 
     pub async fn get_applications(&self) -> Result<Vec<Application>, AppError> {
-        let res = sqlx::query_as!(
-            Application,
-            "SELECT app_id, app_name, topic, enabled from application"
+        let res = sqlx::query_as::<_, Application>(
+            "SELECT app_id, app_name, topic, enabled, sink_type, payment_topic from application",
         )
         .fetch_all(&self.client)
-        .await
-        .expect("Error fetching application list");
+        .await?;
 
         Ok(res)
     }
 
     pub async fn get_application(&self, app_id: String) -> Result<Option<Application>, AppError> {
-        let res = sqlx::query_as!(
-            Application,
-            "SELECT app_id, app_name, topic, enabled from application WHERE app_id = $1",
-            app_id
+        let res = sqlx::query_as::<_, Application>(
+            "SELECT app_id, app_name, topic, enabled, sink_type, payment_topic from application WHERE app_id = $1",
+        )
+        .bind(app_id)
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn create_application(&self, req: &CreateApplicationRequest) -> Result<Application, AppError> {
+        let res = sqlx::query_as::<_, Application>(
+            r#"insert into application (app_id, app_name, topic, enabled, sink_type, app_secret, payment_topic)
+                values ($1, $2, $3, $4, $5, $6, nullif($7, ''))
+                returning app_id, app_name, topic, enabled, sink_type, payment_topic
+            "#,
+        )
+        .bind(&req.app_id)
+        .bind(&req.app_name)
+        .bind(&req.topic)
+        .bind(req.enabled)
+        .bind(&req.sink_type)
+        .bind(&req.app_secret)
+        .bind(&req.payment_topic)
+        .fetch_one(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Applies the fields present in `req`; absent fields keep their value and
+    /// an empty `payment_topic` clears it.
+    pub async fn update_application(
+        &self,
+        app_id: &str,
+        req: &PatchApplicationRequest,
+    ) -> Result<Option<Application>, AppError> {
+        let res = sqlx::query_as::<_, Application>(
+            r#"update application set
+                    app_name = coalesce($2, app_name),
+                    topic = coalesce($3, topic),
+                    enabled = coalesce($4, enabled),
+                    sink_type = coalesce($5, sink_type),
+                    app_secret = coalesce($6, app_secret),
+                    payment_topic = case when $7::varchar is null then payment_topic else nullif($7, '') end
+                where app_id = $1
+                returning app_id, app_name, topic, enabled, sink_type, payment_topic
+            "#,
+        )
+        .bind(app_id)
+        .bind(&req.app_name)
+        .bind(&req.topic)
+        .bind(req.enabled)
+        .bind(&req.sink_type)
+        .bind(&req.app_secret)
+        .bind(&req.payment_topic)
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn set_application_enabled(&self, app_id: &str, enabled: bool) -> Result<Option<Application>, AppError> {
+        let res = sqlx::query_as::<_, Application>(
+            r#"update application set enabled = $2
+                where app_id = $1
+                returning app_id, app_name, topic, enabled, sink_type, payment_topic
+            "#,
         )
+        .bind(app_id)
+        .bind(enabled)
         .fetch_optional(&self.client)
-        .await
-        .expect("Error fetching application by id");
+        .await?;
 
         Ok(res)
     }
 
+    /// Deletes the application together with its registry links. Returns `false`
+    /// when no such application exists.
+    pub async fn delete_application(&self, app_id: &str) -> Result<bool, AppError> {
+        let mut tx = self.client.begin().await?;
+
+        sqlx::query(
+            "delete from application_registry where app_id in (select id from application where app_id = $1)",
+        )
+        .bind(app_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let res = sqlx::query("delete from application where app_id = $1")
+            .bind(app_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Whether `app_id` is registered to the channel `ref_id` of `channel_type`.
+    pub async fn is_registered(&self, channel_type: ChannelType, ref_id: &str, app_id: &str) -> Result<bool, AppError> {
+        let res: (bool,) = sqlx::query_as(
+            r#"select exists (
+                    select 1
+                    from merchant_channel a
+                    join application_registry b on a.id = b.channel_id
+                    join application c on b.app_id = c.id
+                    where a.ref_id = $1 and a.ref_type = $2 and c.app_id = $3
+                )
+            "#,
+        )
+        .bind(ref_id)
+        .bind(channel_type.ref_type())
+        .bind(app_id)
+        .fetch_one(&self.client)
+        .await?;
+
+        Ok(res.0)
+    }
+
+    /// `ref_id`s of the merchant channels registered to an application.
+    pub async fn get_application_ref_ids(&self, app_id: &str) -> Result<Vec<String>, AppError> {
+        let res: Vec<(String,)> = sqlx::query_as(
+            r#"select a.ref_id
+                from merchant_channel a
+                join application_registry b on a.id = b.channel_id
+                join application c on b.app_id = c.id
+                where c.app_id = $1
+            "#,
+        )
+        .bind(app_id)
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(res.into_iter().map(|(ref_id,)| ref_id).collect())
+    }
+
+    /// Returns `None` when the application does not exist.
+    pub async fn get_subscriptions(&self, app_id: &str) -> Result<Option<Vec<Subscription>>, AppError> {
+        let application: Option<(i32,)> = sqlx::query_as("select id from application where app_id = $1")
+            .bind(app_id)
+            .fetch_optional(&self.client)
+            .await?;
+        let Some((id,)) = application else {
+            return Ok(None);
+        };
+
+        let res = sqlx::query_as::<_, Subscription>(
+            "select event_type, topic from application_subscription where app_id = $1 order by event_type",
+        )
+        .bind(id)
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(Some(res))
+    }
+
+    /// Replaces every subscription of `app_id` in one transaction.
+    pub async fn replace_subscriptions(
+        &self,
+        app_id: &str,
+        subscriptions: &[Subscription],
+    ) -> Result<Option<Vec<Subscription>>, AppError> {
+        let mut tx = self.client.begin().await?;
+
+        let application: Option<(i32,)> = sqlx::query_as("select id from application where app_id = $1")
+            .bind(app_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some((id,)) = application else {
+            return Ok(None);
+        };
+
+        sqlx::query("delete from application_subscription where app_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+        for subscription in subscriptions {
+            sqlx::query(
+                r#"insert into application_subscription (app_id, event_type, topic)
+                    values ($1, $2, $3)
+                    on conflict (app_id, event_type) do update set topic = excluded.topic
+                "#,
+            )
+            .bind(id)
+            .bind(&subscription.event_type)
+            .bind(&subscription.topic)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.get_subscriptions(app_id).await
+    }
+
     pub async fn get_merchant_channels(&self) -> Result<Vec<MerchantChannel>, AppError> {
-        let res = sqlx::query_as!(
-            MerchantChannel,
-            "SELECT id, ref_id, name, ref_type, token from merchant_channel"
+        let res = sqlx::query_as::<_, MerchantChannel>(
+            "SELECT id, ref_id, name, ref_type, token, token_key_id, token_dek from merchant_channel"
         )
         .fetch_all(&self.client)
-        .await
-        .expect("Error fetching merchant channel list");
+        .await?;
 
         Ok(res)
     }
@@ -86,34 +293,343 @@ impl Database {
         &self,
         ref_id: String,
     ) -> Result<Option<MerchantChannel>, AppError> {
-        let res = sqlx::query_as!(
-            MerchantChannel,
-            "SELECT id, ref_id, name, ref_type, token from merchant_channel where ref_id = $1",
-            ref_id
+        let res = sqlx::query_as::<_, MerchantChannel>(
+            "SELECT id, ref_id, name, ref_type, token, token_key_id, token_dek from merchant_channel where ref_id = $1",
         )
+        .bind(ref_id)
         .fetch_optional(&self.client)
-        .await
-        .expect("Error fetching merchant channel by id");
+        .await?;
+
+        Ok(res)
+    }
+
+    /// `token` replaces the plaintext `req.token`, which is never written.
+    pub async fn create_merchant_channel(
+        &self,
+        req: &CreateMerchantChannelRequest,
+        token: &EncryptedToken,
+    ) -> Result<MerchantChannel, AppError> {
+        let res = sqlx::query_as::<_, MerchantChannel>(
+            r#"insert into merchant_channel (ref_id, name, ref_type, token, token_key_id, token_dek)
+                values ($1, $2, $3, $4, $5, $6)
+                returning id, ref_id, name, ref_type, token, token_key_id, token_dek
+            "#,
+        )
+        .bind(&req.ref_id)
+        .bind(&req.name)
+        .bind(&req.ref_type)
+        .bind(&token.token)
+        .bind(&token.token_key_id)
+        .bind(&token.token_dek)
+        .fetch_one(&self.client)
+        .await?;
 
         Ok(res)
     }
 
-    pub async fn get_merchant_config(&self, page_id: String) -> Result<Option<MerchantConfig>, AppError> {
-        let res = sqlx::query_as!(
-            MerchantConfig,
-            r#"select a.id as channel_id, b.app_id as "app_id!", c.topic as topic, c.enabled, a.token
+    /// Applies the fields present in `req`; absent fields keep their value.
+    /// A new token is taken from `token` rather than `req.token`.
+    pub async fn update_merchant_channel(
+        &self,
+        ref_id: &str,
+        req: &PatchMerchantChannelRequest,
+        token: Option<&EncryptedToken>,
+    ) -> Result<Option<MerchantChannel>, AppError> {
+        let res = sqlx::query_as::<_, MerchantChannel>(
+            r#"update merchant_channel set
+                    name = coalesce($2, name),
+                    ref_type = coalesce($3, ref_type),
+                    token = case when $4::varchar is null then token else $4 end,
+                    token_key_id = case when $4::varchar is null then token_key_id else $5 end,
+                    token_dek = case when $4::varchar is null then token_dek else $6 end
+                where ref_id = $1
+                returning id, ref_id, name, ref_type, token, token_key_id, token_dek
+            "#,
+        )
+        .bind(ref_id)
+        .bind(&req.name)
+        .bind(&req.ref_type)
+        .bind(token.map(|token| &token.token))
+        .bind(token.and_then(|token| token.token_key_id.as_ref()))
+        .bind(token.and_then(|token| token.token_dek.as_ref()))
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Stores a re-wrapped token, unless another writer replaced it since it
+    /// was read.
+    pub async fn rotate_merchant_channel_token(
+        &self,
+        id: i32,
+        previous: &EncryptedToken,
+        token: &EncryptedToken,
+    ) -> Result<bool, AppError> {
+        let res = sqlx::query(
+            r#"update merchant_channel set token = $2, token_key_id = $3, token_dek = $4
+                where id = $1 and token = $5 and token_key_id is not distinct from $6
+            "#,
+        )
+        .bind(id)
+        .bind(&token.token)
+        .bind(&token.token_key_id)
+        .bind(&token.token_dek)
+        .bind(&previous.token)
+        .bind(&previous.token_key_id)
+        .execute(&self.client)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Deletes the merchant channel together with its registry links. Returns
+    /// `false` when no such channel exists.
+    pub async fn delete_merchant_channel(&self, ref_id: &str) -> Result<bool, AppError> {
+        let mut tx = self.client.begin().await?;
+
+        sqlx::query(
+            "delete from application_registry where channel_id in (select id from merchant_channel where ref_id = $1)",
+        )
+        .bind(ref_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let res = sqlx::query("delete from merchant_channel where ref_id = $1")
+            .bind(ref_id)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    pub async fn get_application_registry(&self, ref_id: &str) -> Result<Vec<ApplicationRegistry>, AppError> {
+        let res = sqlx::query_as::<_, ApplicationRegistry>(
+            r#"select a.ref_id, c.app_id, b.filter
                 from merchant_channel a
-                left join application_registry b
-                on a.id = b.channel_id
-                left join public.application c on b.app_id= c.id where ref_id = $1
-            "#, page_id
-            ).fetch_optional(&self.client)
-            .await
-            .expect("Error fetching merchant channel by id");
+                join application_registry b on a.id = b.channel_id
+                join application c on b.app_id = c.id
+                where a.ref_id = $1
+                order by c.app_id
+            "#,
+        )
+        .bind(ref_id)
+        .fetch_all(&self.client)
+        .await?;
 
         Ok(res)
     }
 
+    /// Resolves the internal ids of a channel and application pair.
+    async fn resolve_registry_ids<'e, E>(executor: E, ref_id: &str, app_id: &str) -> Result<(i32, i32), AppError>
+    where
+        E: sqlx::PgExecutor<'e>,
+    {
+        let res: Option<(i32, i32)> = sqlx::query_as(
+            "select a.id, c.id from merchant_channel a, application c where a.ref_id = $1 and c.app_id = $2",
+        )
+        .bind(ref_id)
+        .bind(app_id)
+        .fetch_optional(executor)
+        .await?;
+
+        res.ok_or_else(AppError::not_found)
+    }
+
+    pub async fn create_application_registry(
+        &self,
+        ref_id: &str,
+        app_id: &str,
+        filter: Option<&RegistryFilter>,
+    ) -> Result<ApplicationRegistry, AppError> {
+        let (channel_id, application_id) = Self::resolve_registry_ids(&self.client, ref_id, app_id).await?;
+
+        let res = sqlx::query(
+            r#"insert into application_registry (channel_id, app_id, filter)
+                select $1, $2, $3
+                where not exists (
+                    select 1 from application_registry where channel_id = $1 and app_id = $2
+                )
+            "#,
+        )
+        .bind(channel_id)
+        .bind(application_id)
+        .bind(filter.map(Json))
+        .execute(&self.client)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(AppError::conflict());
+        }
+
+        Ok(ApplicationRegistry {
+            ref_id: ref_id.to_string(),
+            app_id: app_id.to_string(),
+            filter: filter.cloned().map(Json),
+        })
+    }
+
+    /// Sets the filter of an existing registration. Returns `None` when the
+    /// application is not registered to the channel.
+    pub async fn update_application_registry_filter(
+        &self,
+        ref_id: &str,
+        app_id: &str,
+        filter: Option<&RegistryFilter>,
+    ) -> Result<Option<ApplicationRegistry>, AppError> {
+        let res = sqlx::query(
+            r#"update application_registry set filter = $3
+                where channel_id in (select id from merchant_channel where ref_id = $1)
+                and app_id in (select id from application where app_id = $2)
+            "#,
+        )
+        .bind(ref_id)
+        .bind(app_id)
+        .bind(filter.map(Json))
+        .execute(&self.client)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(ApplicationRegistry {
+            ref_id: ref_id.to_string(),
+            app_id: app_id.to_string(),
+            filter: filter.cloned().map(Json),
+        }))
+    }
+
+    /// Replaces the registry links of `ref_id` with `app_ids` in one
+    /// transaction. Links that stay keep their filter.
+    pub async fn replace_application_registry(
+        &self,
+        ref_id: &str,
+        app_ids: &[String],
+    ) -> Result<Vec<ApplicationRegistry>, AppError> {
+        let mut tx = self.client.begin().await?;
+
+        let channel: Option<(i32,)> = sqlx::query_as("select id from merchant_channel where ref_id = $1")
+            .bind(ref_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let (channel_id,) = channel.ok_or_else(AppError::not_found)?;
+
+        let mut application_ids = Vec::with_capacity(app_ids.len());
+        for app_id in app_ids {
+            let (_, application_id) = Self::resolve_registry_ids(&mut *tx, ref_id, app_id).await?;
+            application_ids.push(application_id);
+        }
+
+        sqlx::query("delete from application_registry where channel_id = $1 and not (app_id = any($2))")
+            .bind(channel_id)
+            .bind(&application_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        for application_id in application_ids {
+            sqlx::query(
+                r#"insert into application_registry (channel_id, app_id)
+                    select $1, $2
+                    where not exists (
+                        select 1 from application_registry where channel_id = $1 and app_id = $2
+                    )
+                "#,
+            )
+            .bind(channel_id)
+            .bind(application_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        self.get_application_registry(ref_id).await
+    }
+
+    pub async fn delete_application_registry(&self, ref_id: &str, app_id: &str) -> Result<bool, AppError> {
+        let res = sqlx::query(
+            r#"delete from application_registry
+                where channel_id in (select id from merchant_channel where ref_id = $1)
+                and app_id in (select id from application where app_id = $2)
+            "#,
+        )
+        .bind(ref_id)
+        .bind(app_id)
+        .execute(&self.client)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Resolves a channel to the applications it routes to with at most one
+    /// query, filling the eligibility cache on the way.
+    pub async fn lookup_merchant(&self, channel_type: ChannelType, ref_id: &str) -> Result<MerchantLookup, AppError> {
+        let cache_key = format!("{}:{}", channel_type.ref_type(), ref_id);
+        if let Some(lookup) = self.merchant_configs.get(&cache_key).await {
+            return Ok(lookup);
+        }
+
+        let rows = sqlx::query_as::<_, MerchantConfigRow>(
+            r#"select a.id as channel_id, c.id as app_id, c.topic, c.enabled, a.token, a.token_key_id, a.token_dek,
+                c.sink_type, c.payment_topic, b.filter,
+                coalesce(
+                    (select json_agg(json_build_object('event_type', s.event_type, 'topic', s.topic))
+                        from application_subscription s where s.app_id = c.id),
+                    '[]'
+                ) as subscriptions
+                from merchant_channel a
+                left join application_registry b on a.id = b.channel_id
+                left join public.application c on b.app_id = c.id
+                where a.ref_id = $1 and a.ref_type = $2
+                order by c.id
+            "#,
+        )
+        .bind(ref_id)
+        .bind(channel_type.ref_type())
+        .fetch_all(&self.client)
+        .await?;
+
+        let lookup = MerchantLookup::from(rows);
+        // Another channel type may still use this ref_id, so only a hit is
+        // conclusive for eligibility.
+        if !matches!(lookup, MerchantLookup::NotEligible) {
+            self.eligibility.insert(ref_id.to_string(), true).await;
+        }
+        self.merchant_configs.insert(cache_key, lookup.clone()).await;
+
+        Ok(lookup)
+    }
+
+    /// App secrets of the applications registered to each `ref_ids` channel
+    /// of `channel_type`, keyed by ref_id.
+    pub async fn get_app_secrets(
+        &self,
+        channel_type: ChannelType,
+        ref_ids: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, AppError> {
+        let res: Vec<(String, String)> = sqlx::query_as(
+            r#"select distinct a.ref_id, c.app_secret
+                from merchant_channel a
+                join application_registry b on a.id = b.channel_id
+                join public.application c on b.app_id = c.id
+                where a.ref_type = $1 and a.ref_id = any($2) and c.app_secret is not null
+            "#,
+        )
+        .bind(channel_type.ref_type())
+        .bind(ref_ids)
+        .fetch_all(&self.client)
+        .await?;
+
+        let mut secrets: HashMap<String, Vec<String>> = HashMap::new();
+        for (ref_id, secret) in res {
+            secrets.entry(ref_id).or_default().push(secret);
+        }
+        Ok(secrets)
+    }
+
     pub async fn is_merchant_channel_eligible(&self, ref_id: String) -> Result<bool, AppError> {
         let cache_result = self.eligibility.get(&ref_id.to_string()).await;
 
@@ -129,8 +645,7 @@ impl Database {
                         ref_id
                     )
                     .fetch_one(&self.client)
-                    .await
-                    .expect("Error fetching merchant channel by id");
+                    .await?;
 
                 let count = res.count;
 
@@ -149,10 +664,469 @@ impl Database {
 
     pub async fn remove_eligible(&self, id: &str) {
         self.eligibility.invalidate(id).await;
+        for channel_type in ChannelType::ALL {
+            self.merchant_configs
+                .invalidate(&format!("{}:{}", channel_type.ref_type(), id))
+                .await;
+        }
     }
 
     pub async fn flush_eligible(&self) {
         self.eligibility.invalidate_all();
+        self.merchant_configs.invalidate_all();
+    }
+
+    /// Writes deliverable messages to the outbox and messages of disabled
+    /// applications to `parked_event`, in one transaction.
+    pub async fn enqueue_outbox(&self, messages: &[NewOutboxMessage], parked: &[NewOutboxMessage]) -> Result<(), AppError> {
+        let mut tx = self.client.begin().await?;
+        insert_outbox(&mut tx, messages, parked).await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Writes everything routed from one webhook body in one transaction: the
+    /// archived entries, the payments with their new `alerts`, and the outbox
+    /// and parked messages. Either all of it is stored or none, so a retried
+    /// body does not archive or record its entries twice. Returns the alerts
+    /// not raised before, with their id.
+    pub async fn record_webhook(
+        &self,
+        archive: &[NewArchivedEntry],
+        payments: &[PaymentRecord],
+        alerts: &[PaymentAlert],
+        messages: &[NewOutboxMessage],
+        parked: &[NewOutboxMessage],
+    ) -> Result<Vec<(i64, PaymentAlert)>, AppError> {
+        self.ensure_archive_partitions(archive).await?;
+
+        let mut tx = self.client.begin().await?;
+        insert_archive(&mut tx, archive).await?;
+        let raised = insert_payments(&mut tx, payments, alerts).await?;
+        insert_outbox(&mut tx, messages, parked).await?;
+        tx.commit().await?;
+
+        Ok(raised)
+    }
+
+    /// Alerts still unpublished after `grace_secs`, oldest first. The grace
+    /// leaves the publish started by the request that raised them time to
+    /// finish.
+    pub async fn unpublished_payment_alerts(&self, grace_secs: f64, limit: i64) -> Result<Vec<(i64, PaymentAlert)>, AppError> {
+        let rows = sqlx::query_as::<_, PaymentAlertRow>(
+            r#"select a.id, a.rule, a.payment_id, a.page_id, p.buyer_id, p.order_id, a.detail, a.event_at, a.trace_id
+                from payment_alert a
+                join payment p on p.payment_id = a.payment_id
+                where a.published_at is null
+                    and a.created_at < now() - make_interval(secs => $1)
+                order by a.id
+                limit $2
+            "#,
+        )
+        .bind(grace_secs)
+        .bind(limit)
+        .fetch_all(&self.client)
+        .await?;
+
+        rows.into_iter().map(TryInto::try_into).collect()
+    }
+
+    pub async fn mark_payment_alerts_published(&self, ids: &[i64]) -> Result<(), AppError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query("update payment_alert set published_at = now() where id = any($1)")
+            .bind(ids)
+            .execute(&self.client)
+            .await?;
+
+        Ok(())
+    }
+
+    /// A page of payments matching `search`, newest first, and the total
+    /// number of matches.
+    pub async fn search_payments(&self, search: &SearchPayments) -> Result<(Vec<Payment>, u64), AppError> {
+        const FILTER: &str = r#"where ($1::varchar is null or page_id = $1)
+                and ($2::varchar is null or buyer_id = $2)
+                and ($3::varchar is null or order_id = $3)
+                and ($4::timestamptz is null or created_at >= $4)
+                and ($5::timestamptz is null or created_at < $5)"#;
+
+        let payments = sqlx::query_as::<_, Payment>(&format!(
+            r#"select payment_id, page_id, buyer_id, order_id, invoice_id, payment_method, amount, currency,
+                    status, is_duplicate, metadata, last_event_at, created_at, updated_at
+                from payment
+                {FILTER}
+                order by created_at desc, payment_id
+                offset $6 limit $7
+            "#
+        ))
+        .bind(&search.page_id)
+        .bind(&search.buyer_id)
+        .bind(&search.order_id)
+        .bind(search.from)
+        .bind(search.to)
+        .bind(i64::try_from(search.offset).map_err(|_| AppError::bad_request())?)
+        .bind(search.limit as i64)
+        .fetch_all(&self.client)
+        .await?;
+
+        let (count,): (i64,) = sqlx::query_as(&format!("select count(*) from payment {FILTER}"))
+            .bind(&search.page_id)
+            .bind(&search.buyer_id)
+            .bind(&search.order_id)
+            .bind(search.from)
+            .bind(search.to)
+            .fetch_one(&self.client)
+            .await?;
+
+        Ok((payments, count as u64))
+    }
+
+    pub async fn get_payment(&self, payment_id: &str) -> Result<Option<Payment>, AppError> {
+        let res = sqlx::query_as::<_, Payment>(
+            r#"select payment_id, page_id, buyer_id, order_id, invoice_id, payment_method, amount, currency,
+                    status, is_duplicate, metadata, last_event_at, created_at, updated_at
+                from payment where payment_id = $1
+            "#,
+        )
+        .bind(payment_id)
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_payment_history(&self, payment_id: &str) -> Result<Vec<PaymentStatusChange>, AppError> {
+        let res = sqlx::query_as::<_, PaymentStatusChange>(
+            r#"select event, status, event_at, trace_id from payment_status_history
+                where payment_id = $1
+                order by event_at, id
+            "#,
+        )
+        .bind(payment_id)
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Moves every parked event of `app_id` to the outbox, oldest first.
+    /// Returns how many were moved.
+    pub async fn replay_parked_events(&self, app_id: &str) -> Result<u64, AppError> {
+        let res = sqlx::query(
+            r#"with moved as (
+                    delete from parked_event
+                    where app_id in (select id from application where app_id = $1)
+                    returning id, app_id, topic, sink_type, payload, trace_id
+                )
+                insert into webhook_outbox (app_id, topic, sink_type, payload, trace_id)
+                select app_id, topic, sink_type, payload, trace_id from moved order by id
+            "#,
+        )
+        .bind(app_id)
+        .execute(&self.client)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    /// Writes accepted webhook entries to `webhook_archive`, creating the
+    /// partition of each day the first time it is seen.
+    /// Creates the missing daily `webhook_archive` partitions for `entries`.
+    async fn ensure_archive_partitions(&self, entries: &[NewArchivedEntry]) -> Result<(), AppError> {
+        for entry in entries {
+            let day = entry.received_at.date_naive();
+            if self.archive_partitions.contains_key(&day) {
+                continue;
+            }
+            sqlx::query("select webhook_archive_ensure_partition($1)")
+                .bind(day)
+                .execute(&self.client)
+                .await?;
+            self.archive_partitions.insert(day, ()).await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_replay_target(&self, app_id: &str) -> Result<Option<ReplayTarget>, AppError> {
+        let res = sqlx::query_as::<_, ReplayTarget>(
+            "select id, topic, sink_type, enabled from application where app_id = $1",
+        )
+        .bind(app_id)
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Archived entries of the channels registered to `req.app_id` that match
+    /// the replay filters, oldest first.
+    pub async fn find_archived_entries(&self, req: &ReplayRequest) -> Result<Vec<ArchivedEntry>, AppError> {
+        let res = sqlx::query_as::<_, ArchivedEntry>(
+            r#"select w.id, w.received_at, w.object, w.page_id, w.event_types, w.request_id, w.payload
+                from webhook_archive w
+                where w.received_at >= $2 and w.received_at < $3
+                and w.page_id in (
+                    select a.ref_id
+                    from merchant_channel a
+                    join application_registry b on a.id = b.channel_id
+                    join public.application c on b.app_id = c.id
+                    where c.app_id = $1
+                )
+                and ($4::varchar is null or w.page_id = $4)
+                and ($5::varchar[] is null or w.event_types && $5::varchar[])
+                order by w.received_at, w.id
+                limit $6
+            "#,
+        )
+        .bind(&req.app_id)
+        .bind(req.from)
+        .bind(req.to)
+        .bind(&req.page_id)
+        .bind(&req.event_types)
+        .bind(i64::from(req.limit))
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn quarantine_webhook(&self, object: Option<&str>, payload: &str, error: &str, trace_id: &str) -> Result<(), AppError> {
+        sqlx::query(
+            r#"insert into webhook_quarantine (object, payload, error, trace_id)
+                values ($1, $2, $3, $4)
+            "#,
+        )
+        .bind(object)
+        .bind(payload)
+        .bind(error)
+        .bind(trace_id)
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps a verified webhook body for the ingest workers to pick up later.
+    /// Writes a webhook to `webhook_spill`, claimable once `delay_secs` have
+    /// passed.
+    pub async fn spill_webhook(&self, payload: &str, trace_id: &str, attempts: i32, delay_secs: f64) -> Result<(), AppError> {
+        sqlx::query(
+            r#"insert into webhook_spill (payload, trace_id, attempts, locked_until)
+                values ($1, $2, $3, now() + make_interval(secs => $4))
+            "#,
+        )
+        .bind(payload)
+        .bind(trace_id)
+        .bind(attempts)
+        .bind(delay_secs)
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Leases up to `limit` spilled webhooks for `lease_secs`, oldest first.
+    /// Rows leased by another worker or instance are skipped; a row whose
+    /// lease runs out before it is deleted or rescheduled is claimed again.
+    pub async fn claim_spilled_webhooks(&self, limit: i64, lease_secs: f64) -> Result<Vec<SpilledWebhook>, AppError> {
+        let mut res = sqlx::query_as::<_, SpilledWebhook>(
+            r#"update webhook_spill
+                set locked_until = now() + make_interval(secs => $2)
+                where id in (
+                    select id from webhook_spill
+                    where locked_until <= now()
+                    order by id
+                    limit $1
+                    for update skip locked
+                )
+                returning id, payload, trace_id, attempts
+            "#,
+        )
+        .bind(limit)
+        .bind(lease_secs)
+        .fetch_all(&self.client)
+        .await?;
+
+        // update .. returning does not keep the subquery order
+        res.sort_by_key(|spilled| spilled.id);
+        Ok(res)
+    }
+
+    /// Puts a leased spilled webhook back with what is left of its `payload`,
+    /// claimable again after `delay_secs`.
+    pub async fn reschedule_spilled_webhook(&self, id: i64, payload: &str, attempts: i32, delay_secs: f64) -> Result<(), AppError> {
+        sqlx::query(
+            r#"update webhook_spill
+                set payload = $2, attempts = $3, locked_until = now() + make_interval(secs => $4)
+                where id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(payload)
+        .bind(attempts)
+        .bind(delay_secs)
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete_spilled_webhook(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query("delete from webhook_spill where id = $1")
+            .bind(id)
+            .execute(&self.client)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn claim_outbox(&self, limit: i64, lease_secs: f64) -> Result<Vec<OutboxMessage>, AppError> {
+        let res = sqlx::query_as::<_, OutboxMessage>(
+            r#"update webhook_outbox
+                set next_attempt_at = now() + make_interval(secs => $2)
+                where id in (
+                    select id from webhook_outbox
+                    where status = 'pending' and next_attempt_at <= now()
+                    order by id
+                    limit $1
+                    for update skip locked
+                )
+                returning id, app_id, topic, sink_type, payload, attempts
+            "#,
+        )
+        .bind(limit)
+        .bind(lease_secs)
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Deletes up to `limit` messages delivered more than `retention_secs`
+    /// ago, returning how many.
+    pub async fn purge_delivered_outbox(&self, retention_secs: f64, limit: i64) -> Result<u64, AppError> {
+        let res = sqlx::query(
+            r#"delete from webhook_outbox
+                where id in (
+                    select id from webhook_outbox
+                    where status = 'delivered' and delivered_at < now() - make_interval(secs => $1)
+                    limit $2
+                )
+            "#,
+        )
+        .bind(retention_secs)
+        .bind(limit)
+        .execute(&self.client)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    pub async fn mark_outbox_delivered(&self, id: i64) -> Result<(), AppError> {
+        sqlx::query(
+            "update webhook_outbox set status = 'delivered', attempts = attempts + 1, delivered_at = now(), last_error = null where id = $1",
+        )
+        .bind(id)
+        .execute(&self.client)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Records a failed attempt. With `retry_in_secs` the message is rescheduled,
+    /// otherwise it is moved to the dead-letter state.
+    pub async fn mark_outbox_failed(&self, id: i64, error: &str, retry_in_secs: Option<f64>) -> Result<(), AppError> {
+        match retry_in_secs {
+            Some(secs) => {
+                sqlx::query(
+                    r#"update webhook_outbox
+                        set attempts = attempts + 1, last_error = $2, next_attempt_at = now() + make_interval(secs => $3)
+                        where id = $1
+                    "#,
+                )
+                .bind(id)
+                .bind(error)
+                .bind(secs)
+                .execute(&self.client)
+                .await?;
+            }
+            None => {
+                sqlx::query(
+                    "update webhook_outbox set status = 'dead', attempts = attempts + 1, last_error = $2 where id = $1",
+                )
+                .bind(id)
+                .bind(error)
+                .execute(&self.client)
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a key by its SHA-256 hex digest and records that it was used.
+    pub async fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKey>, AppError> {
+        let res = sqlx::query_as::<_, ApiKey>(
+            r#"update api_key set last_used_at = now()
+                where key_hash = $1
+                returning id, name, scopes, enabled, app_id
+            "#,
+        )
+        .bind(key_hash)
+        .fetch_optional(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_api_keys(&self) -> Result<Vec<ApiKey>, AppError> {
+        let res = sqlx::query_as::<_, ApiKey>(
+            "select id, name, scopes, enabled, app_id from api_key order by id",
+        )
+        .fetch_all(&self.client)
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Creates a key, bound to `app_id` when given. Not found when that
+    /// application does not exist.
+    pub async fn create_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        scopes: &[String],
+        app_id: Option<&str>,
+    ) -> Result<ApiKey, AppError> {
+        let res = sqlx::query_as::<_, ApiKey>(
+            r#"insert into api_key (name, key_hash, scopes, app_id)
+                select $1, $2, $3, $4
+                where $4::varchar is null or exists (select 1 from application where app_id = $4)
+                returning id, name, scopes, enabled, app_id
+            "#,
+        )
+        .bind(name)
+        .bind(key_hash)
+        .bind(scopes)
+        .bind(app_id)
+        .fetch_optional(&self.client)
+        .await?;
+
+        res.ok_or_else(AppError::not_found)
+    }
+
+    pub async fn delete_api_key(&self, id: i32) -> Result<bool, AppError> {
+        let res = sqlx::query("delete from api_key where id = $1")
+            .bind(id)
+            .execute(&self.client)
+            .await?;
+
+        Ok(res.rows_affected() > 0)
     }
 
     pub async fn get_sequence(&self, id: &str) -> Result<i32, AppError> {
@@ -170,14 +1144,156 @@ impl Database {
 
         let res = sqlx::query!("SELECT data from sequencers where name = $1", id)
             .fetch_one(&self.client)
-            .await
-            .expect("Error fetching merchant channel by id");
+            .await?;
         let seq = res.data;
 
         Ok(seq)
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, thiserror::Error)]
 #[error("sqlx error: {0}")]
 pub struct DbError(#[from] sqlx::Error);
+
+async fn insert_archive(conn: &mut PgConnection, entries: &[NewArchivedEntry]) -> Result<(), AppError> {
+    for entry in entries {
+        sqlx::query(
+            r#"insert into webhook_archive (received_at, object, page_id, event_types, request_id, payload)
+                values ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(entry.received_at)
+        .bind(&entry.object)
+        .bind(&entry.page_id)
+        .bind(&entry.event_types)
+        .bind(&entry.request_id)
+        .bind(&entry.payload)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Upserts the payments in `records` and appends each change to their status
+/// history. The latest event by `event_at` decides the status. Returns the
+/// `alerts` not raised before, with their id.
+async fn insert_payments(
+    conn: &mut PgConnection,
+    records: &[PaymentRecord],
+    alerts: &[PaymentAlert],
+) -> Result<Vec<(i64, PaymentAlert)>, AppError> {
+    for record in records {
+        let event = &record.event;
+        if let Some(payment_id) = &event.payment_id {
+            sqlx::query(
+                r#"insert into payment (payment_id, page_id, buyer_id, order_id, invoice_id, payment_method,
+                        amount, currency, status, is_duplicate, metadata, last_event_at)
+                    values ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
+                    on conflict (payment_id) do update set
+                        buyer_id = coalesce(excluded.buyer_id, payment.buyer_id),
+                        order_id = coalesce(excluded.order_id, payment.order_id),
+                        invoice_id = coalesce(excluded.invoice_id, payment.invoice_id),
+                        payment_method = coalesce(excluded.payment_method, payment.payment_method),
+                        amount = coalesce(excluded.amount, payment.amount),
+                        currency = coalesce(excluded.currency, payment.currency),
+                        status = case when excluded.last_event_at >= payment.last_event_at
+                            then excluded.status else payment.status end,
+                        is_duplicate = payment.is_duplicate or excluded.is_duplicate,
+                        metadata = coalesce(excluded.metadata, payment.metadata),
+                        last_event_at = greatest(excluded.last_event_at, payment.last_event_at),
+                        updated_at = now()
+                "#,
+            )
+            .bind(payment_id)
+            .bind(&event.page_id)
+            .bind(&event.buyer_id)
+            .bind(&event.order_id)
+            .bind(&event.invoice_id)
+            .bind(&event.payment_method)
+            .bind(event.amount)
+            .bind(&event.currency)
+            .bind(&event.status)
+            .bind(event.is_duplicate)
+            .bind(record.metadata.as_ref().map(Json))
+            .bind(record.event_at)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        sqlx::query(
+            r#"insert into payment_status_history (payment_id, invoice_id, page_id, event, status, event_at, trace_id)
+                values ($1, $2, $3, $4, $5, $6, $7)
+                on conflict do nothing
+            "#,
+        )
+        .bind(&event.payment_id)
+        .bind(&event.invoice_id)
+        .bind(&event.page_id)
+        .bind(&event.event)
+        .bind(&event.status)
+        .bind(record.event_at)
+        .bind(&record.trace_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    let mut raised = Vec::new();
+    for alert in alerts {
+        let id: Option<(i64,)> = sqlx::query_as(
+            r#"insert into payment_alert (payment_id, page_id, rule, detail, event_at, trace_id)
+                values ($1, $2, $3, $4, $5, $6)
+                on conflict (payment_id, rule) do nothing
+                returning id
+            "#,
+        )
+        .bind(&alert.payment_id)
+        .bind(&alert.page_id)
+        .bind(alert.rule.as_str())
+        .bind(&alert.detail)
+        .bind(alert.event_at)
+        .bind(&alert.trace_id)
+        .fetch_optional(&mut *conn)
+        .await?;
+        if let Some((id,)) = id {
+            raised.push((id, alert.clone()));
+        }
+    }
+
+    Ok(raised)
+}
+
+async fn insert_outbox(conn: &mut PgConnection, messages: &[NewOutboxMessage], parked: &[NewOutboxMessage]) -> Result<(), AppError> {
+    for message in messages {
+        sqlx::query(
+            r#"insert into webhook_outbox (app_id, topic, sink_type, payload, trace_id)
+                values ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(message.app_id)
+        .bind(&message.topic)
+        .bind(&message.sink_type)
+        .bind(&message.payload)
+        .bind(&message.trace_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    for message in parked {
+        sqlx::query(
+            r#"insert into parked_event (app_id, topic, sink_type, payload, trace_id)
+                values ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(message.app_id)
+        .bind(&message.topic)
+        .bind(&message.sink_type)
+        .bind(&message.payload)
+        .bind(&message.trace_id)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use emit::{__emit_get_event_data, emit, info, warn};
+use moka::future::Cache;
+
+use crate::{
+    cache::CacheService,
+    models::{messenger_webhook::WebhookEntry, whatsapp_webhook::WhatsAppEntry},
+    utils::config::env_or,
+};
+
+const KEY_PREFIX: &str = "femto:dedupe:";
+/// How long dedupe stays on the local cache after Redis failed, before Redis
+/// is tried again.
+const REDIS_RETRY_AFTER: Duration = Duration::from_secs(30);
+
+/// Suppresses events Meta redelivers within `window`. Seen keys are shared
+/// across instances through Redis, checked once per entry in a pipeline; the
+/// local cache answers while Redis is unreachable. After a Redis failure the
+/// local cache is used straight away for `REDIS_RETRY_AFTER`, so an outage
+/// does not cost a timeout per webhook.
+#[derive(Clone)]
+pub struct Deduplicator {
+    cache: CacheService,
+    local: Cache<String, ()>,
+    window: Duration,
+    redis_down_until: Arc<Mutex<Option<Instant>>>,
+}
+
+impl Deduplicator {
+    pub fn new(cache: CacheService, window: Duration) -> Self {
+        let local = Cache::builder()
+            .max_capacity(100_000)
+            .time_to_live(window.max(Duration::from_secs(1)))
+            .build();
+
+        Deduplicator {
+            cache,
+            local,
+            window,
+            redis_down_until: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn from_env(cache: CacheService) -> Self {
+        Self::new(cache, Duration::from_secs(env_or("DEDUPE_WINDOW_SECS", 24 * 60 * 60)))
+    }
+
+    fn enabled(&self) -> bool {
+        !self.window.is_zero()
+    }
+
+    fn redis_down(&self) -> bool {
+        let mut down_until = match self.redis_down_until.lock() {
+            Ok(down_until) => down_until,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        match *down_until {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                *down_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn trip(&self) {
+        let mut down_until = match self.redis_down_until.lock() {
+            Ok(down_until) => down_until,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *down_until = Some(Instant::now() + REDIS_RETRY_AFTER);
+    }
+
+    /// Returns, per key, `true` the first time it is seen within the window.
+    pub async fn first_seen(&self, keys: &[String]) -> Vec<bool> {
+        if !self.enabled() {
+            return vec![true; keys.len()];
+        }
+
+        if !self.redis_down() {
+            let redis_keys = keys
+                .iter()
+                .map(|key| format!("{KEY_PREFIX}{key}"))
+                .collect::<Vec<String>>();
+            match self.cache.set_if_absent_many(&redis_keys, self.window.as_secs()).await {
+                Ok(first) => return first,
+                Err(err) => {
+                    warn!("Dedupe falling back to local cache for {}s, error: {}",
+                        retry_after_secs: REDIS_RETRY_AFTER.as_secs(),
+                        error: err.to_string());
+                    self.trip();
+                }
+            }
+        }
+
+        let mut first = Vec::with_capacity(keys.len());
+        for key in keys {
+            first.push(self.local.entry(key.clone()).or_insert(()).await.is_fresh());
+        }
+        first
+    }
+
+    /// Keeps the events of `events` not seen before, appending their keys to
+    /// `claimed`, and returns the keys of the dropped ones. Events without a
+    /// dedupe key are always kept.
+    async fn retain_first_seen<T>(
+        &self,
+        events: Vec<T>,
+        dedupe_key: impl Fn(&T) -> Option<String>,
+        claimed: &mut Vec<String>,
+    ) -> (Vec<T>, Vec<String>) {
+        let keyed = events
+            .into_iter()
+            .map(|event| (dedupe_key(&event), event))
+            .collect::<Vec<(Option<String>, T)>>();
+        let keys = keyed
+            .iter()
+            .filter_map(|(key, _)| key.clone())
+            .collect::<Vec<String>>();
+        let mut first = self.first_seen(&keys).await.into_iter();
+
+        let mut kept = Vec::with_capacity(keyed.len());
+        let mut dropped = Vec::new();
+        for (key, event) in keyed {
+            match key {
+                Some(key) if first.next().unwrap_or(true) => {
+                    claimed.push(key);
+                    kept.push(event);
+                }
+                Some(key) => dropped.push(key),
+                None => kept.push(event),
+            }
+        }
+        (kept, dropped)
+    }
+
+    /// Forgets keys claimed for events that could not be accepted, so Meta's
+    /// retry of them is not suppressed.
+    pub async fn release(&self, keys: &[String]) {
+        if !self.enabled() || keys.is_empty() {
+            return;
+        }
+
+        for key in keys {
+            self.local.invalidate(key).await;
+        }
+        if self.redis_down() {
+            return;
+        }
+        let redis_keys = keys
+            .iter()
+            .map(|key| format!("{KEY_PREFIX}{key}"))
+            .collect::<Vec<String>>();
+        if let Err(err) = self.cache.delete(&redis_keys).await {
+            warn!("Failed to release dedupe keys, error: {}", error: err.to_string());
+        }
+    }
+
+    /// Drops already-seen events from `entry` and returns the keys it claimed.
+    /// Events without a dedupe key are always kept.
+    pub async fn filter_entry(&self, entry: &mut WebhookEntry) -> Vec<String> {
+        let mut claimed = Vec::new();
+
+        if let Some(messaging) = entry.messaging.take() {
+            let (kept, dropped) = self.retain_first_seen(messaging, |event| event.dedupe_key(), &mut claimed).await;
+            for key in dropped {
+                info!("Dropping duplicate event {}, page ID {}", dedupe_key: key, page_id: entry.id);
+            }
+            entry.messaging = Some(kept);
+        }
+
+        if let Some(changes) = entry.changes.take() {
+            let (kept, dropped) = self.retain_first_seen(changes, |change| change.dedupe_key(), &mut claimed).await;
+            for key in dropped {
+                info!("Dropping duplicate change {}, page ID {}", dedupe_key: key, page_id: entry.id);
+            }
+            entry.changes = Some(kept);
+        }
+
+        claimed
+    }
+
+    /// Drops already-seen messages and statuses from every change of a
+    /// WhatsApp `entry` and returns the keys it claimed.
+    pub async fn filter_whatsapp_entry(&self, entry: &mut WhatsAppEntry) -> Vec<String> {
+        let mut claimed = Vec::new();
+
+        for change in &mut entry.changes {
+            if let Some(messages) = change.value.messages.take() {
+                let (kept, dropped) = self
+                    .retain_first_seen(messages, |message| Some(message.dedupe_key()), &mut claimed)
+                    .await;
+                for key in dropped {
+                    info!("Dropping duplicate message {}, WABA ID {}", dedupe_key: key, waba_id: entry.id);
+                }
+                change.value.messages = Some(kept);
+            }
+
+            if let Some(statuses) = change.value.statuses.take() {
+                let (kept, dropped) = self
+                    .retain_first_seen(statuses, |status| Some(status.dedupe_key()), &mut claimed)
+                    .await;
+                for key in dropped {
+                    info!("Dropping duplicate status {}, WABA ID {}", dedupe_key: key, waba_id: entry.id);
+                }
+                change.value.statuses = Some(kept);
+            }
+        }
+
+        claimed
+    }
+}
@@ -1,12 +1,18 @@
 use axum::{
-    http::StatusCode,
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
-use log::error;
-use serde_json::json;
+use emit::{__emit_get_event_data, emit, error, warn};
+use serde::Serialize;
 use thiserror::Error;
 use tokio::task::JoinError;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::handlers::context::current_request_id;
+
+const PROBLEM_JSON: &str = "application/problem+json";
 
 #[derive(Debug, Error)]
 #[error("...")]
@@ -17,6 +23,36 @@ pub enum AppError {
     #[error("{}", _0)]
     BadRequest(#[from] BadRequest),
 
+    #[error("{}", _0)]
+    Unauthorized(#[from] Unauthorized),
+
+    #[error("{}", _0)]
+    Forbidden(#[from] Forbidden),
+
+    #[error("{}", _0)]
+    Conflict(#[from] Conflict),
+
+    #[error("{}", _0)]
+    ValidationError(#[from] validator::ValidationErrors),
+
+    #[error("{}", _0)]
+    MalformedRequest(#[from] MalformedRequest),
+
+    #[error("{}", _0)]
+    Upstream(#[from] UpstreamError),
+
+    #[error("{}", _0)]
+    ServiceUnavailable(#[from] ServiceUnavailable),
+
+    #[error("Database error: {0}")]
+    DatabaseError(String),
+
+    #[error("Cache error: {0}")]
+    CacheError(String),
+
+    #[error("Timed out: {0}")]
+    Timeout(String),
+
     #[error("{}", _0)]
     InternalServerError(String),
 
@@ -28,12 +64,54 @@ impl AppError {
     fn get_codes(&self) -> (StatusCode, u16) {
         match *self {
             // 4XX Errors
+            AppError::Unauthorized(_) => (StatusCode::UNAUTHORIZED, 40001),
             AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, 40002),
             AppError::NotFound(_) => (StatusCode::NOT_FOUND, 40003),
+            AppError::Forbidden(_) => (StatusCode::FORBIDDEN, 40004),
+            AppError::ValidationError(_) => (StatusCode::BAD_REQUEST, 40005),
+            AppError::Conflict(_) => (StatusCode::CONFLICT, 40006),
+            AppError::Upstream(UpstreamError::RateLimited(_)) => (StatusCode::TOO_MANY_REQUESTS, 40007),
+            AppError::Upstream(UpstreamError::Rejected(_)) => (StatusCode::UNPROCESSABLE_ENTITY, 40008),
+            AppError::MalformedRequest(ref rejection) => (rejection.status, 40009),
 
             // 5XX Errors
             AppError::InternalServerError(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5001),
+            AppError::DatabaseError(_) => (StatusCode::SERVICE_UNAVAILABLE, 5002),
+            AppError::CacheError(_) => (StatusCode::SERVICE_UNAVAILABLE, 5003),
+            AppError::Timeout(_) => (StatusCode::GATEWAY_TIMEOUT, 5004),
             AppError::RunSyncTask(_) => (StatusCode::INTERNAL_SERVER_ERROR, 5005),
+            AppError::Upstream(UpstreamError::Unavailable(_)) => (StatusCode::BAD_GATEWAY, 5006),
+            AppError::Upstream(UpstreamError::Unauthorized(_)) => (StatusCode::BAD_GATEWAY, 5007),
+            AppError::ServiceUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, 5008),
+        }
+    }
+
+    /// Stable slug used in the problem `type`, and the message shown to
+    /// clients. Server and upstream errors never expose their own text.
+    fn describe(&self) -> (&'static str, String) {
+        match self {
+            AppError::Unauthorized(_) => ("unauthorized", "A valid API key or bearer token is required".to_string()),
+            AppError::BadRequest(_) => ("bad-request", "The request is malformed".to_string()),
+            AppError::NotFound(_) => ("not-found", "The requested resource does not exist".to_string()),
+            AppError::Forbidden(_) => ("forbidden", "The credential is not allowed to perform this request".to_string()),
+            AppError::ValidationError(_) => ("validation-failed", "One or more fields are invalid".to_string()),
+            AppError::MalformedRequest(rejection) => ("malformed-request", rejection.detail.clone()),
+            AppError::Conflict(_) => ("conflict", "The request conflicts with the current state of the resource".to_string()),
+            AppError::Upstream(UpstreamError::RateLimited(_)) => {
+                ("upstream-rate-limited", "The Graph API rate limit for the page was reached, retry later".to_string())
+            }
+            AppError::Upstream(UpstreamError::Rejected(_)) => ("upstream-rejected", "The Graph API rejected the request".to_string()),
+            AppError::Upstream(UpstreamError::Unavailable(_)) => ("upstream-unavailable", "The Graph API is unavailable".to_string()),
+            AppError::Upstream(UpstreamError::Unauthorized(_)) => {
+                ("upstream-unauthorized", "The Graph API refused the page access token".to_string())
+            }
+            AppError::ServiceUnavailable(_) => ("service-unavailable", "The service is overloaded, retry later".to_string()),
+            AppError::DatabaseError(_) => ("database-error", "The database is unavailable".to_string()),
+            AppError::CacheError(_) => ("cache-error", "The cache is unavailable".to_string()),
+            AppError::Timeout(_) => ("timeout", "The request timed out".to_string()),
+            AppError::InternalServerError(_) | AppError::RunSyncTask(_) => {
+                ("internal-error", "An internal error occurred".to_string())
+            }
         }
     }
 
@@ -44,27 +122,170 @@ impl AppError {
     pub fn not_found() -> Self {
         AppError::NotFound(NotFound {})
     }
+
+    pub fn unauthorized() -> Self {
+        AppError::Unauthorized(Unauthorized {})
+    }
+
+    pub fn forbidden() -> Self {
+        AppError::Forbidden(Forbidden {})
+    }
+
+    pub fn conflict() -> Self {
+        AppError::Conflict(Conflict {})
+    }
+
+    pub fn service_unavailable() -> Self {
+        AppError::ServiceUnavailable(ServiceUnavailable {})
+    }
+
+    /// A rejection of the extractors in [`crate::handlers::extract`]. Its text
+    /// only describes the client's own input; a rejection caused by the
+    /// server, such as a route missing its path parameters, is an internal
+    /// error.
+    fn rejection(status: StatusCode, detail: String) -> Self {
+        if status.is_server_error() {
+            return AppError::InternalServerError(detail);
+        }
+        AppError::MalformedRequest(MalformedRequest { status, detail })
+    }
 }
 
 impl From<redis::RedisError> for AppError {
     fn from(err: redis::RedisError) -> Self {
+        if err.is_timeout() {
+            AppError::Timeout(err.to_string())
+        } else {
+            AppError::CacheError(err.to_string())
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        AppError::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<lapin::Error> for AppError {
+    fn from(err: lapin::Error) -> Self {
         AppError::InternalServerError(err.to_string())
     }
 }
 
 impl From<sqlx::Error> for AppError {
     fn from(err: sqlx::Error) -> Self {
-        AppError::InternalServerError(err.to_string())
+        match &err {
+            sqlx::Error::RowNotFound => AppError::not_found(),
+            sqlx::Error::PoolTimedOut => AppError::Timeout(err.to_string()),
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() || db_err.is_foreign_key_violation() => {
+                AppError::conflict()
+            }
+            _ => AppError::DatabaseError(err.to_string()),
+        }
+    }
+}
+
+/// An RFC 7807 problem details body.
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    /// Stable error code, see [`AppError::get_codes`].
+    pub code: u16,
+    pub request_id: Option<String>,
+    /// Failed fields of a validation error.
+    pub errors: Option<Vec<FieldError>>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    /// Dotted path of the field, `__all__` for checks across fields.
+    pub field: String,
+    pub code: String,
+    pub message: Option<String>,
+}
+
+/// Flattens nested struct and list errors into `a.b[0].c` paths.
+fn field_errors(errors: &ValidationErrors, prefix: &str, out: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() { field.to_string() } else { format!("{prefix}.{field}") };
+        match kind {
+            ValidationErrorsKind::Field(errors) => out.extend(errors.iter().map(|error| FieldError {
+                field: path.clone(),
+                code: error.code.to_string(),
+                message: error.message.as_ref().map(ToString::to_string),
+            })),
+            ValidationErrorsKind::Struct(errors) => field_errors(errors, &path, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    field_errors(errors, &format!("{path}[{index}]"), out);
+                }
+            }
+        }
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status_code, code) = self.get_codes();
-        let message = self.to_string();
-        let body = Json(json!({ "code": code, "message": message }));
+        let (slug, detail) = self.describe();
+        let request_id = current_request_id();
+
+        if status_code.is_server_error() {
+            error!("Request failed, request ID {}, code {}, error: {}",
+                request_id: request_id.clone().unwrap_or_default(),
+                code: code,
+                error: self.to_string());
+        } else if let AppError::Upstream(err) = &self {
+            warn!("Upstream refused the request, request ID {}, code {}, error: {}",
+                request_id: request_id.clone().unwrap_or_default(),
+                code: code,
+                error: err.to_string());
+        }
+
+        let errors = match &self {
+            AppError::ValidationError(errors) => {
+                let mut fields = Vec::new();
+                field_errors(errors, "", &mut fields);
+                fields.sort_by(|a, b| a.field.cmp(&b.field));
+                Some(fields)
+            }
+            _ => None,
+        };
+
+        let problem = Problem {
+            problem_type: format!("urn:femto:problem:{slug}"),
+            title: status_code.canonical_reason().unwrap_or_default().to_string(),
+            status: status_code.as_u16(),
+            detail,
+            code,
+            request_id,
+            errors,
+        };
 
-        (status_code, body).into_response()
+        let mut response = (status_code, Json(problem)).into_response();
+        response
+            .headers_mut()
+            .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+        response
     }
 }
 
@@ -72,6 +293,78 @@ impl IntoResponse for AppError {
 #[error("Bad Request")]
 pub struct BadRequest {}
 
+/// A body, query string or path the extractors could not read: a missing
+/// content type, invalid JSON or a field of the wrong type.
+#[derive(thiserror::Error, Debug)]
+#[error("Malformed request: {detail}")]
+pub struct MalformedRequest {
+    pub status: StatusCode,
+    pub detail: String,
+}
+
 #[derive(thiserror::Error, Debug)]
 #[error("Not found")]
-pub struct NotFound {}
\ No newline at end of file
+pub struct NotFound {}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Unauthorized")]
+pub struct Unauthorized {}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Forbidden")]
+pub struct Forbidden {}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Conflict")]
+pub struct Conflict {}
+
+#[derive(thiserror::Error, Debug)]
+#[error("Service Unavailable")]
+pub struct ServiceUnavailable {}
+
+/// A failed call to the Meta Graph API.
+#[derive(thiserror::Error, Debug)]
+pub enum UpstreamError {
+    /// Graph throttled the page or app, or the local per-page limit is spent.
+    #[error("Upstream rate limit reached: {0}")]
+    RateLimited(String),
+
+    /// Graph refused the request itself, e.g. an unknown recipient.
+    #[error("Upstream rejected the request: {0}")]
+    Rejected(String),
+
+    /// Refused connections and Graph server errors.
+    #[error("Upstream unavailable: {0}")]
+    Unavailable(String),
+
+    /// The page access token is invalid, expired or lacks a permission.
+    #[error("Upstream authorization failed: {0}")]
+    Unauthorized(String),
+}
+
+impl UpstreamError {
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, UpstreamError::RateLimited(_) | UpstreamError::Unavailable(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upstream_errors_do_not_expose_graph_messages() {
+        let message = "(#100) Invalid recipient 123 (code 100, subcode 2018001, fbtrace_id Abc)".to_string();
+        let errors = [
+            AppError::from(UpstreamError::RateLimited(message.clone())),
+            AppError::from(UpstreamError::Rejected(message.clone())),
+            AppError::from(UpstreamError::Unavailable(message.clone())),
+            AppError::from(UpstreamError::Unauthorized(message.clone())),
+        ];
+
+        for error in errors {
+            let (_, detail) = error.describe();
+            assert!(!detail.contains("fbtrace_id") && !detail.contains("recipient"), "{detail}");
+        }
+    }
+}
@@ -0,0 +1,621 @@
+use crate::{
+    errors::AppError,
+    handlers::{
+        auth::{generate_api_key, hash_api_key, require_scope, scopes},
+        extract::{Json, Path, Query},
+        state::SharedState,
+    },
+    models::{
+        api_key::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse},
+        application::{
+            ApplicationResponse, CreateApplicationRequest, PatchApplicationRequest,
+            UpdateApplicationRequest,
+        },
+        application_registry::{
+            ApplicationRegistry, CreateApplicationRegistryRequest,
+            PatchApplicationRegistryRequest, UpdateApplicationRegistryRequest,
+        },
+        archive::{ReplayRequest, ReplayResponse},
+        ingest::IngestMetricsResponse,
+        merchant_channel::{
+            CreateMerchantChannelRequest, MerchantChannelResponse, PatchMerchantChannelRequest,
+            RotateTokensResponse, UpdateMerchantChannelRequest,
+        },
+        search_application::SearchApplication,
+        subscription::{Subscription, UpdateSubscriptionsRequest},
+    },
+    utils::custom_response::{CustomResponseBuilder, CustomResponseResult as Response},
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    middleware::from_fn,
+    routing::{delete, get, patch, post, put},
+    Router,
+};
+use axum_macros::debug_handler;
+use emit::{__emit_get_event_data, emit, info, warn};
+use validator::Validate;
+
+pub fn create_route() -> Router<SharedState> {
+    let applications = Router::new()
+        .route("/applications", post(create_application_handler))
+        .route(
+            "/applications/:app_id",
+            put(update_application_handler)
+                .patch(patch_application_handler)
+                .delete(delete_application_handler),
+        )
+        .route("/applications/:app_id/subscriptions", put(update_subscriptions_handler))
+        .route("/applications/:app_id/pause", post(pause_application_handler))
+        .route("/applications/:app_id/resume", post(resume_application_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::WRITE_APPLICATIONS, req, next)));
+
+    let applications_read = Router::new()
+        .route("/applications/:app_id/subscriptions", get(get_subscriptions_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::READ_APPLICATIONS, req, next)));
+
+    let merchants = Router::new()
+        .route("/merchants", post(create_merchant_channel_handler))
+        .route(
+            "/merchants/:ref_id",
+            put(update_merchant_channel_handler)
+                .patch(patch_merchant_channel_handler)
+                .delete(delete_merchant_channel_handler),
+        )
+        .route_layer(from_fn(|req, next| require_scope(scopes::WRITE_MERCHANTS, req, next)));
+
+    let registry_read = Router::new()
+        .route("/registry", get(get_application_registry_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::READ_REGISTRY, req, next)));
+
+    let registry_write = Router::new()
+        .route("/registry", post(create_application_registry_handler))
+        .route("/registry/:ref_id", put(update_application_registry_handler))
+        .route(
+            "/registry/:ref_id/:app_id",
+            patch(patch_application_registry_handler).delete(delete_application_registry_handler),
+        )
+        .route_layer(from_fn(|req, next| require_scope(scopes::WRITE_REGISTRY, req, next)));
+
+    let admin = Router::new()
+        .route("/api-keys", get(get_api_keys_handler).post(create_api_key_handler))
+        .route("/api-keys/:id", delete(delete_api_key_handler))
+        .route("/cache/invalidate", post(invalidate_all_handler))
+        .route("/cache/invalidate/:ref_id", post(invalidate_channel_handler))
+        .route("/merchants/tokens/rotate", post(rotate_merchant_tokens_handler))
+        .route("/archive/replay", post(replay_archive_handler))
+        .route("/ingest/metrics", get(ingest_metrics_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::ADMIN, req, next)));
+
+    Router::new()
+        .merge(applications)
+        .merge(applications_read)
+        .merge(merchants)
+        .merge(registry_read)
+        .merge(registry_write)
+        .merge(admin)
+}
+
+/// Drops cached lookups for every channel registered to `app_id`. Runs after
+/// the change is committed, so it never fails the request: when the channels
+/// cannot be listed every entry is dropped instead.
+async fn invalidate_application(state: &SharedState, app_id: &str) {
+    match state.database.get_application_ref_ids(app_id).await {
+        Ok(ref_ids) => {
+            for ref_id in ref_ids {
+                state.invalidator.invalidate(&ref_id).await;
+            }
+        }
+        Err(err) => {
+            warn!("Failed to list channels of application {}, invalidating all, error: {}", app_id: app_id, error: err.to_string());
+            state.invalidator.invalidate_all().await;
+        }
+    }
+}
+
+#[debug_handler]
+pub async fn create_application_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<CreateApplicationRequest>,
+) -> Response<ApplicationResponse> {
+    req.validate()?;
+    let app = state.database.create_application(&req).await?;
+    info!("Application {} created", app_id: app.app_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(ApplicationResponse::from(app))
+        .status_code(StatusCode::CREATED)
+        .build();
+
+    Ok(res)
+}
+
+async fn apply_application_changes(
+    state: &SharedState,
+    app_id: &str,
+    req: PatchApplicationRequest,
+) -> Response<ApplicationResponse> {
+    req.validate()?;
+    let app = state
+        .database
+        .update_application(app_id, &req)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    invalidate_application(state, app_id).await;
+    info!("Application {} updated", app_id: app_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(ApplicationResponse::from(app))
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn update_application_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+    Json(req): Json<UpdateApplicationRequest>,
+) -> Response<ApplicationResponse> {
+    req.validate()?;
+    apply_application_changes(&state, &app_id, req.into()).await
+}
+
+#[debug_handler]
+pub async fn patch_application_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+    Json(req): Json<PatchApplicationRequest>,
+) -> Response<ApplicationResponse> {
+    apply_application_changes(&state, &app_id, req).await
+}
+
+#[debug_handler]
+pub async fn delete_application_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+) -> Response<()> {
+    let ref_ids = state.database.get_application_ref_ids(&app_id).await?;
+    if !state.database.delete_application(&app_id).await? {
+        return Err(AppError::not_found());
+    }
+    for ref_id in ref_ids {
+        state.invalidator.invalidate(&ref_id).await;
+    }
+    info!("Application {} deleted", app_id: app_id);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn pause_application_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+) -> Response<ApplicationResponse> {
+    let app = state
+        .database
+        .set_application_enabled(&app_id, false)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    invalidate_application(&state, &app_id).await;
+    info!("Application {} paused", app_id: app_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(ApplicationResponse::from(app))
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+/// Re-enables an application and queues the events parked while it was paused.
+#[debug_handler]
+pub async fn resume_application_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+) -> Response<ApplicationResponse> {
+    let app = state
+        .database
+        .set_application_enabled(&app_id, true)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    invalidate_application(&state, &app_id).await;
+    let replayed = state.outbox.replay_parked(&app_id).await?;
+    info!("Application {} resumed, {} parked events replayed", app_id: app_id, replayed: replayed);
+
+    let res = CustomResponseBuilder::new()
+        .body(ApplicationResponse::from(app))
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn get_subscriptions_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+) -> Response<Vec<Subscription>> {
+    let subscriptions = state
+        .database
+        .get_subscriptions(&app_id)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+
+    let res = CustomResponseBuilder::new()
+        .body(subscriptions)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn update_subscriptions_handler(
+    State(state): State<SharedState>,
+    Path(app_id): Path<String>,
+    Json(req): Json<UpdateSubscriptionsRequest>,
+) -> Response<Vec<Subscription>> {
+    req.validate()?;
+    let subscriptions = state
+        .database
+        .replace_subscriptions(&app_id, &req.subscriptions)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    invalidate_application(&state, &app_id).await;
+    info!("Application {} subscriptions replaced", app_id: app_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(subscriptions)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn create_merchant_channel_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<CreateMerchantChannelRequest>,
+) -> Response<MerchantChannelResponse> {
+    req.validate()?;
+    let token = state.cipher.encrypt(&req.ref_id, &req.token)?;
+    let channel = state.database.create_merchant_channel(&req, &token).await?;
+    state.invalidator.invalidate(&channel.ref_id).await;
+    info!("Merchant channel {} created", ref_id: channel.ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(MerchantChannelResponse::from(channel))
+        .status_code(StatusCode::CREATED)
+        .build();
+
+    Ok(res)
+}
+
+async fn apply_merchant_channel_changes(
+    state: &SharedState,
+    ref_id: &str,
+    req: PatchMerchantChannelRequest,
+) -> Response<MerchantChannelResponse> {
+    req.validate()?;
+    let token = req.token.as_deref().map(|token| state.cipher.encrypt(ref_id, token)).transpose()?;
+    let channel = state
+        .database
+        .update_merchant_channel(ref_id, &req, token.as_ref())
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    state.invalidator.invalidate(ref_id).await;
+    info!("Merchant channel {} updated", ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(MerchantChannelResponse::from(channel))
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn update_merchant_channel_handler(
+    State(state): State<SharedState>,
+    Path(ref_id): Path<String>,
+    Json(req): Json<UpdateMerchantChannelRequest>,
+) -> Response<MerchantChannelResponse> {
+    req.validate()?;
+    apply_merchant_channel_changes(&state, &ref_id, req.into()).await
+}
+
+#[debug_handler]
+pub async fn patch_merchant_channel_handler(
+    State(state): State<SharedState>,
+    Path(ref_id): Path<String>,
+    Json(req): Json<PatchMerchantChannelRequest>,
+) -> Response<MerchantChannelResponse> {
+    apply_merchant_channel_changes(&state, &ref_id, req).await
+}
+
+#[debug_handler]
+pub async fn delete_merchant_channel_handler(
+    State(state): State<SharedState>,
+    Path(ref_id): Path<String>,
+) -> Response<()> {
+    if !state.database.delete_merchant_channel(&ref_id).await? {
+        return Err(AppError::not_found());
+    }
+    state.invalidator.invalidate(&ref_id).await;
+    info!("Merchant channel {} deleted", ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn get_application_registry_handler(
+    State(state): State<SharedState>,
+    Query(search): Query<SearchApplication>,
+) -> Response<Vec<ApplicationRegistry>> {
+    let ref_id = search.id.ok_or_else(AppError::bad_request)?;
+    let registry = state.database.get_application_registry(&ref_id).await?;
+
+    let res = CustomResponseBuilder::new()
+        .body(registry)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn create_application_registry_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<CreateApplicationRegistryRequest>,
+) -> Response<ApplicationRegistry> {
+    req.validate()?;
+    let registry = state
+        .database
+        .create_application_registry(&req.ref_id, &req.app_id, req.filter.as_ref())
+        .await?;
+    state.invalidator.invalidate(&req.ref_id).await;
+    info!("Application {} registered to channel {}", app_id: req.app_id, ref_id: req.ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(registry)
+        .status_code(StatusCode::CREATED)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn update_application_registry_handler(
+    State(state): State<SharedState>,
+    Path(ref_id): Path<String>,
+    Json(req): Json<UpdateApplicationRegistryRequest>,
+) -> Response<Vec<ApplicationRegistry>> {
+    req.validate()?;
+    let registry = state
+        .database
+        .replace_application_registry(&ref_id, &req.app_ids)
+        .await?;
+    state.invalidator.invalidate(&ref_id).await;
+    info!("Channel {} registry replaced", ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(registry)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn patch_application_registry_handler(
+    State(state): State<SharedState>,
+    Path((ref_id, app_id)): Path<(String, String)>,
+    Json(req): Json<PatchApplicationRegistryRequest>,
+) -> Response<ApplicationRegistry> {
+    req.validate()?;
+    let registry = state
+        .database
+        .update_application_registry_filter(&ref_id, &app_id, req.filter.as_ref())
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    state.invalidator.invalidate(&ref_id).await;
+    info!("Application {} filter on channel {} updated", app_id: app_id, ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .body(registry)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn delete_application_registry_handler(
+    State(state): State<SharedState>,
+    Path((ref_id, app_id)): Path<(String, String)>,
+) -> Response<()> {
+    if !state
+        .database
+        .delete_application_registry(&ref_id, &app_id)
+        .await?
+    {
+        return Err(AppError::not_found());
+    }
+    state.invalidator.invalidate(&ref_id).await;
+    info!("Application {} unregistered from channel {}", app_id: app_id, ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn get_api_keys_handler(State(state): State<SharedState>) -> Response<Vec<ApiKeyResponse>> {
+    let keys = state.database.get_api_keys().await?;
+    let keys = keys
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<ApiKeyResponse>>();
+
+    let res = CustomResponseBuilder::new()
+        .body(keys)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn create_api_key_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<CreateApiKeyRequest>,
+) -> Response<CreatedApiKeyResponse> {
+    req.validate()?;
+    let key = generate_api_key();
+    let created = state
+        .database
+        .create_api_key(&req.name, &hash_api_key(&key), &req.scopes, req.app_id.as_deref())
+        .await?;
+    info!("API key {} created with scopes {}", id: created.id, scopes: created.scopes.join(" "));
+
+    let res = CustomResponseBuilder::new()
+        .body(CreatedApiKeyResponse {
+            id: created.id,
+            name: created.name,
+            scopes: created.scopes,
+            app_id: created.app_id,
+            key,
+        })
+        .status_code(StatusCode::CREATED)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn delete_api_key_handler(
+    State(state): State<SharedState>,
+    Path(id): Path<i32>,
+) -> Response<()> {
+    if !state.database.delete_api_key(id).await? {
+        return Err(AppError::not_found());
+    }
+    state.invalidator.invalidate_api_keys().await;
+    info!("API key {} revoked", id: id);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn invalidate_channel_handler(
+    State(state): State<SharedState>,
+    Path(ref_id): Path<String>,
+) -> Response<()> {
+    state.invalidator.invalidate(&ref_id).await;
+    info!("Eligibility cache invalidated for channel {}", ref_id: ref_id);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn invalidate_all_handler(State(state): State<SharedState>) -> Response<()> {
+    state.invalidator.invalidate_all().await;
+    info!("Eligibility cache invalidated for all channels",);
+
+    let res = CustomResponseBuilder::new()
+        .status_code(StatusCode::NO_CONTENT)
+        .build();
+
+    Ok(res)
+}
+
+/// Re-wraps every page access token with the active encryption key, and
+/// encrypts tokens still stored as plaintext.
+#[debug_handler]
+pub async fn rotate_merchant_tokens_handler(State(state): State<SharedState>) -> Response<RotateTokensResponse> {
+    let mut rotated = 0;
+    let mut failed = Vec::new();
+    for channel in state.database.get_merchant_channels().await? {
+        let token = match state.cipher.rotate(&channel.ref_id, &channel.token) {
+            Ok(Some(token)) => token,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Failed to re-encrypt token of channel {}, error: {}", ref_id: channel.ref_id, error: err.to_string());
+                failed.push(channel.ref_id);
+                continue;
+            }
+        };
+        match state
+            .database
+            .rotate_merchant_channel_token(channel.id, &channel.token, &token)
+            .await
+        {
+            Ok(true) => rotated += 1,
+            Ok(false) => {}
+            Err(err) => {
+                warn!("Failed to store rotated token of channel {}, error: {}", ref_id: channel.ref_id, error: err.to_string());
+                failed.push(channel.ref_id);
+            }
+        }
+    }
+    // cached lookups hold copies of the old ciphertext
+    state.invalidator.invalidate_all().await;
+    info!("Rotated {} merchant channel tokens, {} failed", rotated: rotated, failed: failed.len());
+
+    let res = CustomResponseBuilder::new()
+        .body(RotateTokensResponse { rotated, failed })
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
+
+/// Re-publishes archived webhook entries to the application's topic in the
+/// background, or lists them when `dry_run` is set.
+#[debug_handler]
+pub async fn replay_archive_handler(
+    State(state): State<SharedState>,
+    Json(req): Json<ReplayRequest>,
+) -> Response<ReplayResponse> {
+    req.validate()?;
+    let status_code = if req.dry_run { StatusCode::OK } else { StatusCode::ACCEPTED };
+    let replay = state.replayer.start(req).await?;
+
+    let res = CustomResponseBuilder::new()
+        .body(replay)
+        .status_code(status_code)
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn ingest_metrics_handler(State(state): State<SharedState>) -> Response<IngestMetricsResponse> {
+    let res = CustomResponseBuilder::new()
+        .body(state.ingest.metrics())
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
@@ -1,34 +1,70 @@
 use crate::{
     errors::AppError,
-    handlers::state::SharedState,
+    handlers::{
+        auth::{require_scope, scopes, Principal},
+        extract::{Json, Path, Query},
+        state::SharedState,
+    },
     models::{
         application::ApplicationResponse,
         health_check::{HealtCheckResponse, HealthCheck},
         merchant_channel::{MerchantChannelEligbleResponse, MerchantChannelResponse},
         search_application::SearchApplication,
+        payment::{PaymentResponse, SearchPayments},
+        send::{SendMessageRequest, SendMessageResponse},
     },
-    utils::custom_response::{CustomResponseBuilder, CustomResponseResult as Response},
+    utils::custom_response::{CustomResponseBuilder, CustomResponseResult as Response, ResponsePagination},
 };
 use axum::{
     body::Body,
-    extract::{Query, State},
+    extract::State,
     http::{Request, StatusCode},
-    routing::get,
-    Router,
+    middleware::from_fn,
+    routing::{get, post},
+    Extension, Router,
 };
 use axum_macros::debug_handler;
 use emit::{__emit_get_event_data, emit, info};
+use validator::Validate;
 
-pub fn create_route() -> Router<SharedState> {
+/// Routes reachable without credentials.
+pub fn create_public_route() -> Router<SharedState> {
     Router::new()
         .route("/", get(root))
         .route("/healthcheck", get(healthcheck_handler))
+}
+
+pub fn create_route() -> Router<SharedState> {
+    let applications = Router::new()
         .route("/applications", get(get_applications_handler))
         .route("/application", get(get_application_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::READ_APPLICATIONS, req, next)));
+
+    let merchants = Router::new()
         .route("/merchants", get(get_merchant_channels_handler))
         .route("/merchant", get(get_merchant_channel_handler))
         .route("/eligible", get(is_merchant_channel_eligible_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::READ_MERCHANTS, req, next)));
+
+    let sequence = Router::new()
         .route("/sequence", get(sequence_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::WRITE_SEQUENCE, req, next)));
+
+    let send = Router::new()
+        .route("/send", post(send_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::WRITE_SEND, req, next)));
+
+    let payments = Router::new()
+        .route("/payments", get(search_payments_handler))
+        .route("/payments/:payment_id", get(get_payment_handler))
+        .route_layer(from_fn(|req, next| require_scope(scopes::READ_PAYMENTS, req, next)));
+
+    Router::new()
+        .merge(applications)
+        .merge(merchants)
+        .merge(sequence)
+        .merge(send)
+        .merge(payments)
 }
 
 #[debug_handler]
@@ -193,4 +229,66 @@ pub async fn sequence_handler(
     Ok(result)
 }
 
+#[debug_handler]
+pub async fn send_handler(
+    State(state): State<SharedState>,
+    Extension(principal): Extension<Principal>,
+    Json(req): Json<SendMessageRequest>,
+) -> Response<SendMessageResponse> {
+    req.validate()?;
+    state.sender.authorize(&principal, &req.page_id).await?;
+    let response = state.sender.send(&req, &principal.subject).await?;
+
+    let res = CustomResponseBuilder::new()
+        .body(response)
+        .status_code(StatusCode::OK)
+        .build();
 
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn search_payments_handler(
+    State(state): State<SharedState>,
+    Query(search): Query<SearchPayments>,
+) -> Response<Vec<PaymentResponse>> {
+    search.validate()?;
+    let (payments, count) = state.database.search_payments(&search).await?;
+    let payments = payments
+        .into_iter()
+        .map(Into::into)
+        .collect::<Vec<PaymentResponse>>();
+
+    let res = CustomResponseBuilder::new()
+        .body(payments)
+        .status_code(StatusCode::OK)
+        .pagination(ResponsePagination {
+            count,
+            offset: search.offset,
+            limit: search.limit,
+        })
+        .build();
+
+    Ok(res)
+}
+
+#[debug_handler]
+pub async fn get_payment_handler(
+    State(state): State<SharedState>,
+    Path(payment_id): Path<String>,
+) -> Response<PaymentResponse> {
+    let payment = state
+        .database
+        .get_payment(&payment_id)
+        .await?
+        .ok_or_else(AppError::not_found)?;
+    let mut payment = PaymentResponse::from(payment);
+    payment.history = Some(state.database.get_payment_history(&payment_id).await?);
+
+    let res = CustomResponseBuilder::new()
+        .body(payment)
+        .status_code(StatusCode::OK)
+        .build();
+
+    Ok(res)
+}
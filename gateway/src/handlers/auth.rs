@@ -0,0 +1,280 @@
+use std::{env, fs, sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use emit::{__emit_get_event_data, emit, info, warn};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use moka::future::Cache;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::{database::Database, errors::AppError, handlers::state::SharedState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+pub mod scopes {
+    pub const ADMIN: &str = "admin";
+    pub const READ_APPLICATIONS: &str = "read:applications";
+    pub const WRITE_APPLICATIONS: &str = "write:applications";
+    pub const READ_MERCHANTS: &str = "read:merchants";
+    pub const WRITE_MERCHANTS: &str = "write:merchants";
+    pub const READ_REGISTRY: &str = "read:registry";
+    pub const WRITE_REGISTRY: &str = "write:registry";
+    pub const WRITE_SEQUENCE: &str = "write:sequence";
+    pub const WRITE_SEND: &str = "write:send";
+    pub const READ_PAYMENTS: &str = "read:payments";
+
+    pub const ALL: &[&str] = &[
+        ADMIN,
+        READ_APPLICATIONS,
+        WRITE_APPLICATIONS,
+        READ_MERCHANTS,
+        WRITE_MERCHANTS,
+        READ_REGISTRY,
+        WRITE_REGISTRY,
+        WRITE_SEQUENCE,
+        WRITE_SEND,
+        READ_PAYMENTS,
+    ];
+}
+
+/// The authenticated caller, available to handlers as a request extension.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub subject: String,
+    pub scopes: Vec<String>,
+    /// Application the caller acts for, from the key's `app_id` or the
+    /// token's `app_id` claim.
+    pub app_id: Option<String>,
+}
+
+impl Principal {
+    /// `admin` implies every other scope.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope || s == scopes::ADMIN)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Claims {
+    sub: String,
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    scopes: Option<Vec<String>>,
+    #[serde(default)]
+    app_id: Option<String>,
+}
+
+impl Claims {
+    /// Accepts both the OAuth2 space-delimited `scope` claim and a `scopes` array.
+    fn into_principal(self) -> Principal {
+        let mut scopes = self.scopes.unwrap_or_default();
+        if let Some(scope) = self.scope {
+            scopes.extend(scope.split_whitespace().map(str::to_string));
+        }
+
+        Principal {
+            subject: self.sub,
+            scopes,
+            app_id: self.app_id,
+        }
+    }
+}
+
+struct JwtConfig {
+    hs256: Option<DecodingKey>,
+    rs256: Option<DecodingKey>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtConfig {
+    fn from_env() -> Self {
+        let hs256 = env::var("JWT_HS256_SECRET")
+            .ok()
+            .map(|secret| DecodingKey::from_secret(secret.as_bytes()));
+        let rs256 = env::var("JWT_RS256_PUBLIC_KEY")
+            .ok()
+            .map(|key| Self::load_rsa_key(&key).expect("env::JWT_RS256_PUBLIC_KEY is not a valid RSA public key"));
+
+        JwtConfig {
+            hs256,
+            rs256,
+            issuer: env::var("JWT_ISSUER").ok(),
+            audience: env::var("JWT_AUDIENCE").ok(),
+        }
+    }
+
+    /// Accepts the PEM itself or a path to it, as either an SPKI `PUBLIC KEY`
+    /// or a PKCS#1 `RSA PUBLIC KEY`.
+    fn load_rsa_key(value: &str) -> Result<DecodingKey, String> {
+        let pem_text = if value.trim_start().starts_with("-----BEGIN") {
+            value.to_string()
+        } else {
+            fs::read_to_string(value).map_err(|err| err.to_string())?
+        };
+        let parsed = pem::parse(&pem_text).map_err(|err| err.to_string())?;
+
+        match parsed.tag() {
+            "RSA PUBLIC KEY" => Ok(DecodingKey::from_rsa_der(parsed.contents())),
+            "PUBLIC KEY" => DecodingKey::from_rsa_pem(pem_text.as_bytes()).map_err(|err| err.to_string()),
+            tag => Err(format!("unsupported PEM block: {tag}")),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.hs256.is_some() || self.rs256.is_some()
+    }
+
+    fn verify(&self, token: &str) -> Result<Principal, AppError> {
+        let header = jsonwebtoken::decode_header(token).map_err(|_| AppError::unauthorized())?;
+        let key = match header.alg {
+            Algorithm::HS256 => self.hs256.as_ref(),
+            Algorithm::RS256 => self.rs256.as_ref(),
+            _ => None,
+        }
+        .ok_or_else(AppError::unauthorized)?;
+
+        let mut validation = Validation::new(header.alg);
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        let data = jsonwebtoken::decode::<Claims>(token, key, &validation).map_err(|err| {
+            info!("Rejecting bearer token, error: {}", error: err.to_string());
+            AppError::unauthorized()
+        })?;
+
+        Ok(data.claims.into_principal())
+    }
+}
+
+/// Resolves API keys and JWT bearer tokens to a [`Principal`].
+#[derive(Clone)]
+pub struct Authenticator {
+    database: Database,
+    jwt: Arc<JwtConfig>,
+    bootstrap_key_hash: Option<String>,
+    keys: Cache<String, Option<Principal>>,
+}
+
+impl Authenticator {
+    pub fn from_env(database: Database) -> Self {
+        let jwt = JwtConfig::from_env();
+        if jwt.enabled() {
+            info!("JWT bearer authentication enabled, issuer: {}", issuer: jwt.issuer.clone().unwrap_or_default());
+        }
+
+        Authenticator {
+            database,
+            jwt: Arc::new(jwt),
+            bootstrap_key_hash: env::var("ADMIN_API_KEY").ok().map(|key| hash_api_key(&key)),
+            keys: Cache::builder()
+                .max_capacity(1_000)
+                .time_to_live(Duration::from_secs(60))
+                .build(),
+        }
+    }
+
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Principal, AppError> {
+        let credential = extract_credential(headers).ok_or_else(AppError::unauthorized)?;
+
+        if self.jwt.enabled() && credential.split('.').count() == 3 {
+            return self.jwt.verify(credential);
+        }
+
+        let key_hash = hash_api_key(credential);
+        if self.bootstrap_key_hash.as_deref() == Some(key_hash.as_str()) {
+            return Ok(Principal {
+                subject: "bootstrap".to_string(),
+                scopes: vec![scopes::ADMIN.to_string()],
+                app_id: None,
+            });
+        }
+
+        if let Some(principal) = self.keys.get(&key_hash).await {
+            return principal.ok_or_else(AppError::unauthorized);
+        }
+
+        let principal = self
+            .database
+            .find_api_key(&key_hash)
+            .await?
+            .filter(|key| key.enabled)
+            .map(|key| Principal {
+                subject: format!("api-key:{}", key.id),
+                scopes: key.scopes,
+                app_id: key.app_id,
+            });
+        self.keys.insert(key_hash, principal.clone()).await;
+
+        principal.ok_or_else(|| {
+            warn!("Rejecting unknown or disabled API key",);
+            AppError::unauthorized()
+        })
+    }
+
+    /// Forgets cached key lookups, e.g. after a key is revoked.
+    pub fn invalidate_keys(&self) {
+        self.keys.invalidate_all();
+    }
+}
+
+/// Reads the caller's credential from `Authorization: Bearer <token>` or `X-Api-Key`.
+fn extract_credential(headers: &HeaderMap) -> Option<&str> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+
+    bearer
+        .or_else(|| headers.get(API_KEY_HEADER).and_then(|h| h.to_str().ok()))
+        .map(str::trim)
+}
+
+/// Issues a new random key. Only its hash is persisted.
+pub fn generate_api_key() -> String {
+    format!("femto_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+pub fn hash_api_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+pub async fn auth_middleware(
+    State(state): State<SharedState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Result<Response, AppError> {
+    let principal = state.auth.authenticate(request.headers()).await?;
+    request.extensions_mut().insert(principal);
+
+    Ok(next.run(request).await)
+}
+
+/// Rejects the request unless the authenticated principal holds `scope`. Must
+/// run inside [`auth_middleware`].
+pub async fn require_scope(scope: &'static str, request: Request<Body>, next: Next) -> Result<Response, AppError> {
+    let principal = request
+        .extensions()
+        .get::<Principal>()
+        .ok_or_else(AppError::unauthorized)?;
+
+    if !principal.has_scope(scope) {
+        info!("Principal {} is missing scope {}", subject: principal.subject, scope: scope);
+        return Err(AppError::forbidden());
+    }
+
+    Ok(next.run(request).await)
+}
@@ -2,6 +2,18 @@ use axum::body::Body;
 use axum::http::Uri;
 use axum::middleware::Next;
 use tower_request_id::RequestId;
+use uuid::Uuid;
+
+tokio::task_local! {
+    /// Request id of the request being handled, for code without access to
+    /// the [`RequestContext`] extension such as error responses.
+    static REQUEST_ID: String;
+}
+
+/// `None` outside of a request, e.g. on background workers.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
 
 #[derive(Clone, Debug)]
 pub struct RequestContext {
@@ -9,17 +21,25 @@ pub struct RequestContext {
     pub request_id: String,
 }
 
-pub async fn context_middleware(request: axum::http::Request<Body>, next: Next) -> axum::response::Response {
+pub async fn context_middleware(mut request: axum::http::Request<Body>, next: Next) -> axum::response::Response {
     let uri = request.uri().clone();
-    let request_ext = request.extensions().clone();
-    let request_id = request_ext.get::<RequestId>().map(|r| &r.0).unwrap();
+    // RequestIdLayer sets the id before this runs; a fresh one keeps tracing
+    // working if the layers are ever reordered
+    let request_id = request
+        .extensions()
+        .get::<RequestId>()
+        .map(|r| r.0.to_string())
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let context = RequestContext {
+        uri,
+        request_id,
+    };
 
-    let mut response = next.run(request).await;
+    request.extensions_mut().insert(context.clone());
 
-    response.extensions_mut().insert(RequestContext {
-        uri,
-        request_id: request_id.to_string(),
-    });
+    let mut response = REQUEST_ID.scope(context.request_id.clone(), next.run(request)).await;
+
+    response.extensions_mut().insert(context);
 
     response
 }
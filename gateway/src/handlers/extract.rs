@@ -0,0 +1,79 @@
+//! `Json`, `Query` and `Path` extractors that answer a malformed request with
+//! the usual problem details instead of axum's plain text rejection.
+
+use axum_macros::{FromRequest, FromRequestParts};
+
+use crate::errors::AppError;
+
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+        routing::post,
+        Router,
+    };
+    use http_body_util::BodyExt;
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct Page {
+        #[allow(dead_code)]
+        limit: u32,
+    }
+
+    async fn problem(request: Request<Body>) -> (StatusCode, String, serde_json::Value) {
+        let app = Router::new().route(
+            "/pages/:id",
+            post(|Path(_): Path<u32>, Query(_): Query<Page>, Json(_): Json<Page>| async { "ok" }),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let content_type = response.headers()[header::CONTENT_TYPE].to_str().unwrap().to_string();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        (status, content_type, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn request(uri: &str, content_type: &str, body: &'static str) -> Request<Body> {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejections_are_problem_details() {
+        let cases = [
+            (request("/pages/abc?limit=1", "application/json", r#"{"limit": 1}"#), StatusCode::BAD_REQUEST),
+            (request("/pages/1?limit=x", "application/json", r#"{"limit": 1}"#), StatusCode::BAD_REQUEST),
+            (request("/pages/1?limit=1", "text/plain", r#"{"limit": 1}"#), StatusCode::UNSUPPORTED_MEDIA_TYPE),
+            (request("/pages/1?limit=1", "application/json", r#"{"limit": "#), StatusCode::BAD_REQUEST),
+            (request("/pages/1?limit=1", "application/json", r#"{"limit": "x"}"#), StatusCode::UNPROCESSABLE_ENTITY),
+        ];
+
+        for (request, status) in cases {
+            let uri = request.uri().to_string();
+            let (actual, content_type, problem) = problem(request).await;
+            assert_eq!(actual, status, "{uri}");
+            assert_eq!(content_type, "application/problem+json", "{uri}");
+            assert_eq!(problem["type"], "urn:femto:problem:malformed-request", "{uri}");
+            assert_eq!(problem["code"], 40009, "{uri}");
+            assert!(!problem["detail"].as_str().unwrap().is_empty(), "{uri}");
+        }
+    }
+}
@@ -1,108 +1,659 @@
-use std::env;
+use std::{collections::HashMap, env};
 use axum::{
-    extract::Query,
+    extract::MatchedPath,
     routing::{get, post},
-    Router, Json,
+    Router,
     };
 use axum::extract::State;
+use axum::Extension;
+use axum::http::HeaderMap;
 use axum_macros::debug_handler;
+use bytes::Bytes;
+use chrono::Utc;
+use serde_json::value::RawValue;
 
 
-use emit::{__emit_get_event_data, emit, info};
-use crate::{handlers::state::SharedState, models::messenger_webhook::MessengerWebhook};
-use crate::models::messenger_webhook::MessengerVerifysubscription;
+use emit::{__emit_get_event_data, emit, info, warn};
+use crate::{errors::AppError, handlers::state::SharedState, ingest::IngestJob};
+use crate::handlers::context::RequestContext;
+use crate::handlers::extract::Query;
+use crate::models::messenger_webhook::{
+    EnvelopeEntry, MessengerVerifysubscription, RawWrappedMessage, WebhookEntry, WebhookEnvelope, WrappedMessage,
+};
+use crate::models::merchant_channel::ChannelType;
+use crate::models::archive::NewArchivedEntry;
+use crate::models::merchant_config::{MerchantConfig, MerchantLookup};
+use crate::models::outbox::NewOutboxMessage;
+use crate::models::payment::PaymentRecord;
+use crate::models::payment_alert::PaymentAlert;
+use crate::models::payment_event::WrappedPaymentEvent;
+use crate::models::whatsapp_webhook::{WhatsAppEntry, WrappedWhatsAppMessage};
+use crate::routing::{DisabledAppPolicy, ForwardMode};
+use crate::utils::signature::{parse_signature, verify_signature, SIGNATURE_HEADER};
 
 pub fn create_route() -> Router<SharedState> {
     Router::new()
         .route("/webhook/messenger", post(messenger_post_handler))
         .route("/webhook/messenger", get(messenger_get_handler))
+        .route("/webhook/instagram", post(messenger_post_handler))
+        .route("/webhook/instagram", get(messenger_get_handler))
+        .route("/webhook/whatsapp", post(messenger_post_handler))
+        .route("/webhook/whatsapp", get(messenger_get_handler))
 }
 
 #[debug_handler]
 async fn messenger_get_handler(
     State(_state): State<SharedState>,
     Query(query): Query<MessengerVerifysubscription>,
-) -> String {
-    let fb_verify_token = env::var("FACEBOOK_WEBHOOK_VERIFY_TOKEN").expect("env::FACEBOOK_WEBHOOK_VERIFY_TOKEN is missing");
+) -> Result<String, AppError> {
+    let fb_verify_token = env::var("FACEBOOK_WEBHOOK_VERIFY_TOKEN")
+        .map_err(|_| AppError::InternalServerError("env::FACEBOOK_WEBHOOK_VERIFY_TOKEN is missing".to_string()))?;
     let verify_token = match query.hub_verify_token {
         Some(token) => token,
         None => {
-            return "No verify token".to_string();
+            return Ok("No verify token".to_string());
         }
     };
 
     let hub_mode = match query.hub_mode {
         Some(mode) => mode,
         None => {
-            return "No hub mode".to_string();
+            return Ok("No hub mode".to_string());
         }
     };
 
     let hub_challenge = match query.hub_challenge {
         Some(challenge) => challenge,
         None => {
-            return "No hub challenge".to_string();
+            return Ok("No hub challenge".to_string());
         }
     };
 
     if hub_mode == "subscribe" && verify_token == fb_verify_token {
-        hub_challenge.to_string()
+        Ok(hub_challenge.to_string())
     } else {
-        "Veirification failed".to_string()
+        Ok("Veirification failed".to_string())
     }
 }
 
+/// Checks the `X-Hub-Signature-256` header against the raw request body and
+/// returns the entries it authenticates, see [`signed_entries`]. A body that
+/// could not be parsed can only be verified with `FACEBOOK_APP_SECRET`.
+async fn verify_webhook_signature(
+    state: &SharedState,
+    headers: &HeaderMap,
+    body: &Bytes,
+    envelope: Option<&WebhookEnvelope>,
+) -> Result<Vec<Box<RawValue>>, AppError> {
+    let signature = match headers.get(SIGNATURE_HEADER).and_then(|h| h.to_str().ok()) {
+        Some(signature) => signature,
+        None => {
+            info!("Rejecting webhook without {} header", header: SIGNATURE_HEADER);
+            return Err(AppError::unauthorized());
+        }
+    };
+    let signature = parse_signature(signature).ok_or_else(AppError::unauthorized)?;
+    let fallback = env::var("FACEBOOK_APP_SECRET").ok();
+
+    let Some(envelope) = envelope else {
+        return match &fallback {
+            Some(secret) if verify_signature(secret, body, &signature) => Ok(Vec::new()),
+            _ => {
+                info!("Webhook signature mismatch on an unparsable body", );
+                Err(AppError::forbidden())
+            }
+        };
+    };
+
+    let channel_type = ChannelType::from_object(&envelope.object);
+    let secrets = match channel_type {
+        Some(channel_type) => state.database.get_app_secrets(channel_type, &envelope.ref_ids()).await?,
+        None => HashMap::new(),
+    };
+    let signed = signed_entries(envelope, channel_type, &secrets, fallback.as_deref(), body, &signature);
+
+    if signed.is_empty() {
+        info!("Webhook signature mismatch, channels: {}", channels: envelope.ref_ids().join(","));
+        return Err(AppError::forbidden());
+    }
+    if signed.len() < envelope.entry.len() {
+        warn!("Dropping {} of {} webhook entries not signed by their channel's app secret",
+            dropped: envelope.entry.len() - signed.len(),
+            total: envelope.entry.len());
+    }
+    Ok(signed)
+}
+
+/// The entries of `envelope` whose every channel, scoped by `channel_type`,
+/// has an app secret that signed `body`. A channel with no registered
+/// secret, and an entry naming no channel, need `fallback` to have signed
+/// it. One tenant's secret therefore cannot vouch for another tenant's
+/// entries in the same body.
+fn signed_entries(
+    envelope: &WebhookEnvelope,
+    channel_type: Option<ChannelType>,
+    secrets: &HashMap<String, Vec<String>>,
+    fallback: Option<&str>,
+    body: &[u8],
+    signature: &[u8],
+) -> Vec<Box<RawValue>> {
+    let fallback_signed = fallback.is_some_and(|secret| verify_signature(secret, body, signature));
+    let channel_signed = |ref_id: &String| match secrets.get(ref_id) {
+        Some(secrets) => secrets.iter().any(|secret| verify_signature(secret, body, signature)),
+        None => fallback_signed,
+    };
+
+    envelope
+        .entry
+        .iter()
+        .filter(|entry| {
+            let ref_ids = match (channel_type, serde_json::from_str::<EnvelopeEntry>(entry.get())) {
+                (Some(channel_type), Ok(head)) => head.ref_ids(channel_type),
+                _ => Vec::new(),
+            };
+            if ref_ids.is_empty() {
+                fallback_signed
+            } else {
+                ref_ids.iter().all(channel_signed)
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/// Messages collected from one webhook request, written to the outbox together.
+#[derive(Default)]
+struct Batch {
+    messages: Vec<NewOutboxMessage>,
+    parked: Vec<NewOutboxMessage>,
+    payments: Vec<PaymentRecord>,
+    archive: Vec<NewArchivedEntry>,
+    claimed_keys: Vec<String>,
+}
+
+impl Batch {
+    /// Archives the original bytes of an entry accepted for `ref_id`.
+    fn archive(&mut self, context: &RequestContext, object: &str, ref_id: &str, event_types: Vec<String>, raw_entry: &RawValue) {
+        self.archive.push(NewArchivedEntry {
+            received_at: Utc::now(),
+            object: object.to_string(),
+            page_id: ref_id.to_string(),
+            event_types,
+            request_id: context.request_id.clone(),
+            payload: raw_entry.get().to_string(),
+        });
+    }
+
+    fn merge(&mut self, other: Batch) {
+        self.messages.extend(other.messages);
+        self.parked.extend(other.parked);
+        self.payments.extend(other.payments);
+        self.archive.extend(other.archive);
+        self.claimed_keys.extend(other.claimed_keys);
+    }
+
+    /// Queues `payload` for `app_config`, honouring the disabled-app policy.
+    fn push(&mut self, state: &SharedState, app_config: &MerchantConfig, topic: String, payload: String, trace_id: &str) {
+        let (topic, parked) = match (&state.disabled_app_policy, app_config.enabled) {
+            (_, true) => (topic, false),
+            (DisabledAppPolicy::Drop, false) => return,
+            (DisabledAppPolicy::Park, false) => (topic, true),
+            (DisabledAppPolicy::Fallback(fallback), false) => (fallback.clone(), false),
+        };
+
+        let message = NewOutboxMessage::new(
+            app_config.app_id,
+            topic,
+            app_config.sink_type.clone(),
+            payload,
+            Some(trace_id.to_string()),
+        );
+        if parked {
+            self.parked.push(message);
+        } else {
+            self.messages.push(message);
+        }
+    }
+}
+
+async fn lookup_apps(
+    state: &SharedState,
+    channel_type: ChannelType,
+    ref_id: &str,
+) -> Result<Option<Vec<MerchantConfig>>, AppError> {
+    let channel = channel_type.ref_type();
+    match state.database.lookup_merchant(channel_type, ref_id).await? {
+        MerchantLookup::Configured(app_configs) => {
+            for app_config in &app_configs {
+                info!("{} {} configuration, topic: {}, app_id: {}, enabled: {}, sink: {}",
+                    channel: channel,
+                    ref_id: ref_id,
+                    topic: app_config.topic,
+                    app_id: app_config.app_id,
+                    enabled: app_config.enabled,
+                    sink: app_config.sink_type);
+            }
+            Ok(Some(app_configs))
+        }
+        MerchantLookup::Unconfigured => {
+            info!("No merchant config for {} ID {} not found", channel: channel, ref_id: ref_id);
+            Ok(None)
+        }
+        MerchantLookup::NotEligible => {
+            info!("{} ID {} is NOT eligible", channel: channel, ref_id: ref_id);
+            Ok(None)
+        }
+    }
+}
+
+async fn quarantine(state: &SharedState, context: &RequestContext, object: Option<&str>, payload: &[u8], error: String) -> Result<(), AppError> {
+    warn!("Quarantining webhook payload, request ID {}, error: {}", request_id: context.request_id, error: error);
+    state
+        .database
+        .quarantine_webhook(object, &String::from_utf8_lossy(payload), &error, &context.request_id)
+        .await
+}
+
+/// Parses the entry into the typed model, then applies dedupe, per-app
+/// filters and subscriptions before re-serializing. An entry that does not
+/// parse is quarantined. Instagram entries share the Messenger model.
+async fn collect_typed(
+    state: &SharedState,
+    context: &RequestContext,
+    channel_type: ChannelType,
+    object: &str,
+    raw_entry: &RawValue,
+    batch: &mut Batch,
+) -> Result<(), AppError> {
+    let mut entry = match serde_json::from_str::<WebhookEntry>(raw_entry.get()) {
+        Ok(entry) => entry,
+        Err(err) => {
+            return quarantine(state, context, Some(object), raw_entry.get().as_bytes(), err.to_string()).await;
+        }
+    };
+    let page_id = entry.id.clone();
+    let Some(app_configs) = lookup_apps(state, channel_type, &page_id).await? else {
+        return Ok(());
+    };
+
+    batch.claimed_keys.extend(state.dedupe.filter_entry(&mut entry).await);
+    if entry.is_empty() {
+        info!("Page {} entry only contained duplicates, skipping", page_id: page_id);
+        return Ok(());
+    }
+    batch.archive(context, object, &page_id, entry.event_types(), raw_entry);
+    batch.payments.extend(
+        entry
+            .changes
+            .iter()
+            .flatten()
+            .filter_map(|change| PaymentRecord::from_change(change, &context.request_id)),
+    );
+
+    for app_config in app_configs {
+        if !app_config.enabled && state.disabled_app_policy == DisabledAppPolicy::Drop {
+            info!("Application {} is disabled, dropping page {} entry", app_id: app_config.app_id, page_id: page_id);
+            continue;
+        }
+
+        let mut page_entry = match &app_config.filter {
+            Some(filter) => filter.apply(&entry),
+            None => entry.clone(),
+        };
+
+        for payment_event in app_config.take_payments(&mut page_entry) {
+            let message = WrappedPaymentEvent {
+                trace_id: context.request_id.clone(),
+                payment_event,
+            };
+            let json_str = serde_json::to_string(&message)
+                .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+            info!("receiving payment event: {}", webhook_payload: json_str);
+            let topic = app_config.payment_topic.clone().unwrap_or_default();
+            batch.push(state, &app_config, topic, json_str, &context.request_id);
+        }
+
+        if page_entry.is_empty() {
+            info!("Page {} entry filtered out for app_id {}", page_id: page_id, app_id: app_config.app_id);
+            continue;
+        }
+
+        for (topic, page_entry) in app_config.route(page_entry) {
+            let message = WrappedMessage {
+                trace_id: context.request_id.clone(),
+                object: object.to_string(),
+                page_entry,
+            };
+            let json_str = serde_json::to_string(&message)
+                .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+            info!("receiving message: {}", webhook_payload: json_str);
+            batch.push(state, &app_config, topic, json_str, &context.request_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Forwards the entry's original bytes to every application of its channel.
+/// A WhatsApp entry can span several phone numbers and goes once to each
+/// application registered to any of them. Dedupe, filters and subscriptions
+/// need the typed model and are skipped; payment changes are still recorded
+/// when the entry also parses into it.
+async fn collect_raw(
+    state: &SharedState,
+    context: &RequestContext,
+    channel_type: ChannelType,
+    object: &str,
+    raw_entry: &RawValue,
+    batch: &mut Batch,
+) -> Result<(), AppError> {
+    let ref_ids = match serde_json::from_str::<EnvelopeEntry>(raw_entry.get()) {
+        Ok(head) => head.ref_ids(channel_type),
+        Err(err) => {
+            return quarantine(state, context, Some(object), raw_entry.get().as_bytes(), err.to_string()).await;
+        }
+    };
+    if ref_ids.is_empty() {
+        let error = "entry has no channel id to route by".to_string();
+        return quarantine(state, context, Some(object), raw_entry.get().as_bytes(), error).await;
+    }
+
+    let mut app_configs: Vec<MerchantConfig> = Vec::new();
+    for ref_id in &ref_ids {
+        let Some(configs) = lookup_apps(state, channel_type, ref_id).await? else {
+            continue;
+        };
+        batch.archive(context, object, ref_id, raw_event_types(channel_type, raw_entry), raw_entry);
+        for config in configs {
+            if !app_configs.iter().any(|existing| existing.app_id == config.app_id) {
+                app_configs.push(config);
+            }
+        }
+    }
+    if app_configs.is_empty() {
+        return Ok(());
+    }
+    batch.payments.extend(raw_payments(channel_type, raw_entry, &context.request_id));
+
+    let message = RawWrappedMessage {
+        trace_id: &context.request_id,
+        object,
+        page_entry: raw_entry,
+    };
+    let json_str = serde_json::to_string(&message)
+        .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+    info!("receiving message: {}", webhook_payload: json_str);
+    for app_config in app_configs {
+        batch.push(state, &app_config, app_config.topic.clone(), json_str.clone(), &context.request_id);
+    }
+
+    Ok(())
+}
+
+/// Event types of an entry forwarded as is, empty when it does not parse.
+fn raw_event_types(channel_type: ChannelType, raw_entry: &RawValue) -> Vec<String> {
+    match channel_type {
+        ChannelType::WhatsApp => serde_json::from_str::<WhatsAppEntry>(raw_entry.get())
+            .map(|entry| entry.event_types())
+            .unwrap_or_default(),
+        _ => serde_json::from_str::<WebhookEntry>(raw_entry.get())
+            .map(|entry| entry.event_types())
+            .unwrap_or_default(),
+    }
+}
+
+/// Payment and invoice changes of an entry forwarded as is, empty when it
+/// does not parse. WhatsApp entries carry none.
+fn raw_payments(channel_type: ChannelType, raw_entry: &RawValue, trace_id: &str) -> Vec<PaymentRecord> {
+    if channel_type == ChannelType::WhatsApp {
+        return Vec::new();
+    }
+    serde_json::from_str::<WebhookEntry>(raw_entry.get())
+        .map(|entry| {
+            entry
+                .changes
+                .iter()
+                .flatten()
+                .filter_map(|change| PaymentRecord::from_change(change, trace_id))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Splits the WhatsApp entry by phone number id and forwards each part,
+/// deduped, to the applications registered to that number. Account-level
+/// changes go to the applications registered to the WABA id. Filters and
+/// subscriptions only know Messenger events and do not apply.
+async fn collect_whatsapp(
+    state: &SharedState,
+    context: &RequestContext,
+    object: &str,
+    raw_entry: &RawValue,
+    batch: &mut Batch,
+) -> Result<(), AppError> {
+    let entry = match serde_json::from_str::<WhatsAppEntry>(raw_entry.get()) {
+        Ok(entry) => entry,
+        Err(err) => {
+            return quarantine(state, context, Some(object), raw_entry.get().as_bytes(), err.to_string()).await;
+        }
+    };
+
+    let routing_ids = entry.routing_ids();
+    if routing_ids.is_empty() {
+        info!("WABA {} entry has no changes, skipping", waba_id: entry.id);
+    }
+    for routing_id in routing_ids {
+        let Some(app_configs) = lookup_apps(state, ChannelType::WhatsApp, &routing_id).await? else {
+            continue;
+        };
+
+        let mut entry = entry.for_routing_id(&routing_id);
+        batch.claimed_keys.extend(state.dedupe.filter_whatsapp_entry(&mut entry).await);
+        if entry.is_empty() {
+            info!("WhatsApp channel {} entry only contained duplicates, skipping", ref_id: routing_id);
+            continue;
+        }
+        batch.archive(context, object, &routing_id, entry.event_types(), raw_entry);
+
+        let message = WrappedWhatsAppMessage {
+            trace_id: context.request_id.clone(),
+            object: object.to_string(),
+            entry,
+        };
+        let json_str = serde_json::to_string(&message)
+            .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+        info!("receiving message: {}", webhook_payload: json_str);
+        for app_config in app_configs {
+            batch.push(state, &app_config, app_config.topic.clone(), json_str.clone(), &context.request_id);
+        }
+    }
+
+    Ok(())
+}
+
+/// Routes a verified webhook body to the outbox. Runs on the ingest workers,
+/// see [`crate::ingest::Ingest`].
+///
+/// Each entry is collected on its own, so an entry whose lookups fail does
+/// not hold up the rest of the body; the failed entries are returned to be
+/// retried. An error means nothing was written.
+pub async fn process_webhook(
+    state: &SharedState,
+    context: &RequestContext,
+    envelope: &WebhookEnvelope,
+) -> Result<Vec<Box<RawValue>>, AppError> {
+    let Some(channel_type) = ChannelType::from_object(&envelope.object) else {
+        info!("Received unsupported object, Got {}", object: envelope.object);
+        return Ok(Vec::new());
+    };
+
+    let mut batch = Batch::default();
+    let mut failed = Vec::new();
+    for raw_entry in &envelope.entry {
+        let mut entry_batch = Batch::default();
+        let object = envelope.object.as_str();
+        let result = match (channel_type, &state.forward_mode) {
+            (_, ForwardMode::Raw) => {
+                collect_raw(state, context, channel_type, object, raw_entry, &mut entry_batch).await
+            }
+            (ChannelType::WhatsApp, ForwardMode::Typed) => {
+                collect_whatsapp(state, context, object, raw_entry, &mut entry_batch).await
+            }
+            (_, ForwardMode::Typed) => {
+                collect_typed(state, context, channel_type, object, raw_entry, &mut entry_batch).await
+            }
+        };
+
+        match result {
+            Ok(()) => batch.merge(entry_batch),
+            Err(err) => {
+                warn!("Webhook entry failed, request ID {}, error: {}", request_id: context.request_id, error: err.to_string());
+                state.dedupe.release(&entry_batch.claimed_keys).await;
+                failed.push(raw_entry.clone());
+            }
+        }
+    }
+
+    let alerts = batch
+        .payments
+        .iter()
+        .flat_map(|record| state.alerts.evaluate(record))
+        .collect::<Vec<PaymentAlert>>();
+    let result = state
+        .database
+        .record_webhook(&batch.archive, &batch.payments, &alerts, &batch.messages, &batch.parked)
+        .await;
+    match result {
+        Ok(raised) => {
+            if !batch.messages.is_empty() {
+                state.outbox.wake();
+            }
+            state.alerts.spawn_publish(raised);
+        }
+        Err(err) => {
+            state.dedupe.release(&batch.claimed_keys).await;
+            return Err(err);
+        }
+    }
+
+    Ok(failed)
+}
+
+/// The webhook object each route accepts, so a payload cannot be routed to
+/// another product's channels by posting it to the wrong subscription URL.
+fn route_object(path: &str) -> Option<&'static str> {
+    match path {
+        "/webhook/messenger" => Some("page"),
+        "/webhook/instagram" => Some("instagram"),
+        "/webhook/whatsapp" => Some("whatsapp_business_account"),
+        _ => None,
+    }
+}
+
+/// Answers 2xx as soon as the signature checks out and the body is queued
+/// for the ingest workers: a body that does not parse is quarantined instead
+/// of rejected, since rejected deliveries make Meta retry and eventually
+/// disable the subscription. A full queue answers `503` under the `reject`
+/// overflow policy.
 #[debug_handler]
 async fn messenger_post_handler(
     State(state): State<SharedState>,
-    Json(payload): Json<MessengerWebhook>,
-
-) -> String {
-
-    let work_payload = payload.clone();
-    let object = work_payload.object;
-    //let page_id = Some(payload.   entry);
-
-    if object == "page" {
-
-        for entry in work_payload.entry.iter() {
-            let page_id = entry.id.clone();
-            let eligible = state
-                .database
-                .is_merchant_channel_eligible(page_id.clone())
-                .await.unwrap();
-
-            if eligible {
-                info!("Eligibility: {}, Page ID {} is eligible", eligible: eligible, page_id: page_id.clone());
-
-                let app_config = state.database.get_merchant_config(page_id.clone()).await.unwrap();
-                match  app_config {
-                    Some(app_config) => {
-                        info!("Page {} configuration, topic: {}, app_id: {}, enabled: {}",
-                            page_id: page_id,
-                            topic: app_config.topic,
-                            app_id: app_config.app_id,
-                            enabled: app_config.enabled);
-                        let json_payload = payload.clone();
-                        let json_str = serde_json::to_string(&json_payload).unwrap();
-                        info!("receiving message: {}", webhook_payload: json_str);
-                        let _ = state.cache.publish(app_config.topic, json_str).await.unwrap();
-                    }
-                    None => {
-                        info!("No merchant config for page ID {} not found", page_id: page_id);
-                    }
-                }
-            } else {
-                info!("Eligibility: {}, Page ID {} is NOT eligible", eligible: eligible, page_id: page_id.clone());
-            }
+    Extension(context): Extension<RequestContext>,
+    path: MatchedPath,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<String, AppError> {
+    let envelope = serde_json::from_slice::<WebhookEnvelope>(&body);
+    let signed = verify_webhook_signature(&state, &headers, &body, envelope.as_ref().ok()).await?;
+
+    let envelope = match envelope {
+        Ok(envelope) => envelope,
+        Err(err) => {
+            quarantine(&state, &context, None, &body, err.to_string()).await?;
+            return Ok("{\"success\":true}".to_string());
         }
+    };
+    if route_object(path.as_str()) != Some(envelope.object.as_str()) {
+        info!("Rejecting {} object posted to {}", object: envelope.object, path: path.as_str());
+        return Err(AppError::bad_request());
+    }
+
+    let (body, envelope) = if signed.len() < envelope.entry.len() {
+        let envelope = WebhookEnvelope {
+            object: envelope.object,
+            entry: signed,
+        };
+        let body = serde_json::to_vec(&envelope).map_err(|err| AppError::InternalServerError(err.to_string()))?;
+        (Bytes::from(body), envelope)
     } else {
-        info!("Received non-page object, Got {}", object: object);
+        (body, envelope)
+    };
+    state.ingest.submit(IngestJob::new(context, body, envelope)).await?;
+
+    Ok("{\"success\":true}".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(secret: &str, body: &[u8]) -> Vec<u8> {
+        use hmac::{Hmac, Mac};
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body);
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn entry_ids(entries: &[Box<RawValue>]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| serde_json::from_str::<EnvelopeEntry>(entry.get()).unwrap().id)
+            .collect()
+    }
+
+    #[test]
+    fn signed_entries_only_keeps_entries_of_the_signing_tenant() {
+        let body = br#"{"object":"page","entry":[{"id":"page-a","time":1},{"id":"page-b","time":1},{"id":"page-c","time":1}]}"#;
+        let envelope = serde_json::from_slice::<WebhookEnvelope>(body).unwrap();
+        let secrets = HashMap::from([
+            ("page-a".to_string(), vec!["secret-a".to_string()]),
+            ("page-b".to_string(), vec!["secret-b".to_string()]),
+        ]);
+
+        let signed = signed_entries(&envelope, Some(ChannelType::Page), &secrets, Some("fallback"), body, &sign("secret-a", body));
+        assert_eq!(entry_ids(&signed), vec!["page-a"]);
+
+        // the fallback secret only vouches for channels without their own secret
+        let signed = signed_entries(&envelope, Some(ChannelType::Page), &secrets, Some("fallback"), body, &sign("fallback", body));
+        assert_eq!(entry_ids(&signed), vec!["page-c"]);
+
+        let signed = signed_entries(&envelope, Some(ChannelType::Page), &secrets, None, body, &sign("other", body));
+        assert!(signed.is_empty());
     }
 
-    // println!("{:?}", serde_json::to_string(&debug_obj).unwrap());
+    #[test]
+    fn signed_entries_needs_every_channel_of_a_whatsapp_entry() {
+        let body = br#"{"object":"whatsapp_business_account","entry":[{"id":"waba","changes":[
+            {"field":"messages","value":{"metadata":{"phone_number_id":"phone-a"}}},
+            {"field":"messages","value":{"metadata":{"phone_number_id":"phone-b"}}}]}]}"#;
+        let envelope = serde_json::from_slice::<WebhookEnvelope>(body).unwrap();
+        let secrets = HashMap::from([
+            ("phone-a".to_string(), vec!["secret-a".to_string()]),
+            ("phone-b".to_string(), vec!["secret-b".to_string(), "secret-a".to_string()]),
+        ]);
 
+        let signed = signed_entries(&envelope, Some(ChannelType::WhatsApp), &secrets, None, body, &sign("secret-a", body));
+        assert_eq!(entry_ids(&signed), vec!["waba"]);
 
+        let signed = signed_entries(&envelope, Some(ChannelType::WhatsApp), &secrets, None, body, &sign("secret-b", body));
+        assert!(signed.is_empty());
+    }
 
-    "{\"success\":true}".to_string()
+    #[test]
+    fn route_object_matches_each_webhook_route() {
+        assert_eq!(route_object("/webhook/messenger"), Some("page"));
+        assert_eq!(route_object("/webhook/instagram"), Some("instagram"));
+        assert_eq!(route_object("/webhook/whatsapp"), Some("whatsapp_business_account"));
+        assert_eq!(route_object("/webhook/other"), None);
+    }
 }
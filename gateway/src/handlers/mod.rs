@@ -1,17 +1,16 @@
-use crate::{cache::CacheService, database::Database, handlers::state::SharedState};
+use crate::{handlers::{auth::auth_middleware, state::SharedState}, utils::config::env_or};
 use axum::{
     body::Body,
-    http::{header,Method, Request, Response as AxumResponse},
+    http::{header, HeaderName, HeaderValue, Method, Request, Response as AxumResponse},
     Router,
 };
 use emit::{__emit_get_event_data, emit, error, info};
 use std::time::Duration;
-use slog::Logger;
 use tower_request_id::{RequestId, RequestIdLayer};
 use tower_http::{
     classify::ServerErrorsFailureClass,
     compression::CompressionLayer,
-    cors::{Any, CorsLayer},
+    cors::{AllowOrigin, Any, CorsLayer},
     sensitive_headers::SetSensitiveHeadersLayer,
     trace::TraceLayer
 };
@@ -19,32 +18,38 @@ use tracing::Span;
 use context::{RequestContext};
 use crate::handlers::context::context_middleware;
 
+pub mod admin;
 pub mod api;
+pub mod auth;
+pub mod context;
+pub mod extract;
 pub mod messenger;
 pub mod state;
-mod context;
 
-pub fn router(database: Database, cache: CacheService, logger: Logger) -> Router {
-    Router::new()
-        .layer(SetSensitiveHeadersLayer::new(std::iter::once(
-            header::AUTHORIZATION,
-        )))
-        .layer(get_cors_layer())
+pub fn router(state: SharedState) -> Router {
+    let protected = Router::new()
         .merge(self::api::create_route())
+        .merge(self::admin::create_route())
+        .route_layer(axum::middleware::from_fn_with_state(state.clone(), auth_middleware));
+
+    Router::new()
+        .merge(self::api::create_public_route())
+        .merge(protected)
         .merge(self::messenger::create_route())
         .layer(axum::middleware::from_fn(context_middleware))
         .layer(
             TraceLayer::new_for_http()
                 .on_request(|request: &Request<Body>, _span: &Span| {
-                    let trace_id = request.extensions().get::<RequestId>().unwrap();
-                    info!("incoming request, request ID {}, URL: {},", request_id: trace_id.to_string(), url: request.uri().to_string());
+                    let trace_id = request.extensions().get::<RequestId>().map(ToString::to_string).unwrap_or_default();
+                    info!("incoming request, request ID {}, URL: {},", request_id: trace_id, url: request.uri().to_string());
                 })
                 .on_response(
                     |response: &AxumResponse<Body>, latency: Duration, _span: &Span| {
                         let in_ms =
                             latency.as_secs() * 1000 + latency.subsec_nanos() as u64 / 1_000_000;
-                        let request_context = response.extensions().get::<RequestContext>().unwrap();
-                        info!("request processed in ms {}, request ID {}, URL: {}", response_time: in_ms, request_id: request_context.request_id, request_uri: request_context.uri.to_string());
+                        if let Some(request_context) = response.extensions().get::<RequestContext>() {
+                            info!("request processed in ms {}, request ID {}, URL: {}", response_time: in_ms, request_id: request_context.request_id, request_uri: request_context.uri.to_string());
+                        }
                     },
                 )
                 .on_failure(
@@ -53,17 +58,38 @@ pub fn router(database: Database, cache: CacheService, logger: Logger) -> Router
                     },
                 ),
         )
+        .layer(get_cors_layer())
+        .layer(SetSensitiveHeadersLayer::new([
+            header::AUTHORIZATION,
+            HeaderName::from_static(auth::API_KEY_HEADER),
+        ]))
         .layer(RequestIdLayer)
         .layer(CompressionLayer::new())
-        .with_state(SharedState { database, cache, logger })
+        .with_state(state)
 }
 
 fn get_cors_layer() -> CorsLayer {
+    let origins = env_or("CORS_ALLOWED_ORIGINS", String::new());
+    let allow_origin = if origins.trim() == "*" {
+        AllowOrigin::from(Any)
+    } else {
+        // no origins configured means browsers cannot call the API cross-origin
+        AllowOrigin::list(
+            origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        )
+    };
+
     CorsLayer::new()
-        // allow `GET` and `POST` when accessing the resource
-        .allow_methods([Method::GET, Method::POST])
-        // allow requests from any origin
-        .allow_origin(Any)
+        // allow reads and the admin write methods when accessing the resource
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::PATCH, Method::DELETE])
+        .allow_headers([
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            HeaderName::from_static(auth::API_KEY_HEADER),
+        ])
+        .allow_origin(allow_origin)
 }
-
-
@@ -1,10 +1,22 @@
 use axum_macros::FromRef;
 use slog::Logger;
-use crate::{cache::CacheService, database::Database};
+use crate::{alerts::PaymentAlerts, archive::Replayer, cache::CacheService, handlers::auth::Authenticator, database::Database, dedupe::Deduplicator, ingest::Ingest, invalidation::Invalidator, routing::{DisabledAppPolicy, ForwardMode}, outbox::Outbox, send::Sender, sinks::MessageSinks, utils::token_cipher::TokenCipher};
 
 #[derive(Clone, FromRef)]
 pub struct SharedState {
     pub(crate) database: Database,
     pub(crate) cache: CacheService,
+    pub(crate) sinks: MessageSinks,
+    pub(crate) outbox: Outbox,
+    pub(crate) dedupe: Deduplicator,
+    pub(crate) auth: Authenticator,
+    pub(crate) invalidator: Invalidator,
+    pub(crate) disabled_app_policy: DisabledAppPolicy,
+    pub(crate) forward_mode: ForwardMode,
+    pub(crate) sender: Sender,
+    pub(crate) cipher: TokenCipher,
+    pub(crate) alerts: PaymentAlerts,
+    pub(crate) replayer: Replayer,
+    pub(crate) ingest: Ingest,
     pub(crate) logger: Logger,
 }
\ No newline at end of file
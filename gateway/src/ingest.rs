@@ -0,0 +1,433 @@
+use std::{
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::http::Uri;
+use bytes::Bytes;
+use emit::{__emit_get_event_data, emit, error, info, warn};
+use serde_json::value::RawValue;
+use tokio::{
+    sync::{
+        mpsc::{self, error::TrySendError},
+        watch, Mutex,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    database::Database,
+    errors::AppError,
+    handlers::{context::RequestContext, messenger::process_webhook, state::SharedState},
+    models::{ingest::IngestMetricsResponse, messenger_webhook::WebhookEnvelope},
+    utils::config::env_parse,
+};
+
+/// What happens to a webhook when the ingest queue is full.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowPolicy {
+    /// Answer `503` so Meta delivers it again later.
+    Reject,
+    /// Write it to `webhook_spill` and answer `200`.
+    Spill,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(OverflowPolicy::Reject),
+            "spill" => Ok(OverflowPolicy::Spill),
+            other => Err(AppError::InternalServerError(format!("unknown ingest overflow policy: {other}"))),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IngestConfig {
+    pub capacity: usize,
+    pub workers: usize,
+    pub overflow: OverflowPolicy,
+    pub max_attempts: i32,
+    pub spill_poll_interval: Duration,
+    pub spill_lease: Duration,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    pub shutdown_timeout: Duration,
+}
+
+impl IngestConfig {
+    /// Panics on an `INGEST_*` value that does not parse, or on a zero
+    /// capacity, worker count or attempt limit.
+    pub fn from_env() -> Self {
+        let config = IngestConfig {
+            capacity: env_parse("INGEST_QUEUE_CAPACITY", 1_000_usize),
+            workers: env_parse("INGEST_WORKERS", 8_usize),
+            overflow: env_parse("INGEST_OVERFLOW_POLICY", OverflowPolicy::Spill),
+            max_attempts: env_parse("INGEST_MAX_ATTEMPTS", 5),
+            spill_poll_interval: Duration::from_millis(env_parse("INGEST_SPILL_POLL_INTERVAL_MS", 1_000)),
+            spill_lease: Duration::from_secs(env_parse("INGEST_SPILL_LEASE_SECS", 300)),
+            base_backoff: Duration::from_millis(env_parse("INGEST_BASE_BACKOFF_MS", 1_000)),
+            max_backoff: Duration::from_secs(env_parse("INGEST_MAX_BACKOFF_SECS", 300)),
+            shutdown_timeout: Duration::from_secs(env_parse("INGEST_SHUTDOWN_TIMEOUT_SECS", 30)),
+        };
+        assert!(config.capacity > 0, "env::INGEST_QUEUE_CAPACITY must be at least 1");
+        assert!(config.workers > 0, "env::INGEST_WORKERS must be at least 1");
+        assert!(config.max_attempts > 0, "env::INGEST_MAX_ATTEMPTS must be at least 1");
+        config
+    }
+}
+
+/// A verified webhook body waiting to be routed.
+pub struct IngestJob {
+    pub context: RequestContext,
+    pub body: Bytes,
+    pub envelope: WebhookEnvelope,
+    /// Failed processing attempts so far.
+    pub attempts: i32,
+    /// The `webhook_spill` row leased for this job, removed once it is done.
+    pub spill_id: Option<i64>,
+    pub queued_at: Instant,
+}
+
+impl IngestJob {
+    pub fn new(context: RequestContext, body: Bytes, envelope: WebhookEnvelope) -> Self {
+        IngestJob {
+            context,
+            body,
+            envelope,
+            attempts: 0,
+            spill_id: None,
+            queued_at: Instant::now(),
+        }
+    }
+
+    /// The same job carrying only `entries`, so a retry skips the entries
+    /// that already went through. Keeps the whole body if it cannot be
+    /// re-serialized.
+    fn with_entries(self, entries: Vec<Box<RawValue>>) -> IngestJob {
+        let envelope = WebhookEnvelope {
+            object: self.envelope.object.clone(),
+            entry: entries,
+        };
+        match serde_json::to_vec(&envelope) {
+            Ok(body) => IngestJob {
+                body: Bytes::from(body),
+                envelope,
+                ..self
+            },
+            Err(_) => self,
+        }
+    }
+}
+
+#[derive(Default)]
+struct IngestMetrics {
+    accepted: AtomicU64,
+    processed: AtomicU64,
+    failed: AtomicU64,
+    rejected: AtomicU64,
+    spilled: AtomicU64,
+    busy_workers: AtomicU64,
+    max_queue_wait_ms: AtomicU64,
+}
+
+/// Decouples acknowledging a webhook from routing it: the handler only
+/// verifies and queues the body, and a pool of workers does the lookups,
+/// archiving and outbox writes. Bodies that do not fit the queue, or whose
+/// processing failed, go through `webhook_spill` and are picked up again when
+/// the queue has room, failed ones after a backoff; after `max_attempts`
+/// failures they are quarantined. A spilled body is leased while queued and
+/// only deleted once processed, so a crash cannot lose it.
+///
+/// Bodies queued in memory are already acknowledged: a crash loses them, a
+/// graceful shutdown spills them, see [`Ingest::shutdown`].
+#[derive(Clone)]
+pub struct Ingest {
+    database: Database,
+    sender: mpsc::Sender<IngestJob>,
+    receiver: Arc<Mutex<mpsc::Receiver<IngestJob>>>,
+    metrics: Arc<IngestMetrics>,
+    config: IngestConfig,
+    closing: Arc<watch::Sender<bool>>,
+}
+
+impl Ingest {
+    pub fn new(database: Database, config: IngestConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.capacity);
+        Ingest {
+            database,
+            sender,
+            receiver: Arc::new(Mutex::new(receiver)),
+            metrics: Arc::new(IngestMetrics::default()),
+            config,
+            closing: Arc::new(watch::Sender::new(false)),
+        }
+    }
+
+    pub async fn submit(&self, job: IngestJob) -> Result<(), AppError> {
+        let job = match self.sender.try_send(job) {
+            Ok(()) => {
+                self.metrics.accepted.fetch_add(1, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(TrySendError::Full(job)) | Err(TrySendError::Closed(job)) => job,
+        };
+
+        match self.config.overflow {
+            OverflowPolicy::Reject => {
+                self.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                warn!("Ingest queue full, rejecting webhook, request ID {}", request_id: job.context.request_id);
+                Err(AppError::service_unavailable())
+            }
+            OverflowPolicy::Spill => {
+                self.spill(&job, job.attempts, Duration::ZERO).await?;
+                self.metrics.accepted.fetch_add(1, Ordering::Relaxed);
+                warn!("Ingest queue full, spilled webhook, request ID {}", request_id: job.context.request_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes `job` to `webhook_spill`, due after `delay`. A job that came
+    /// from there updates its row instead.
+    async fn spill(&self, job: &IngestJob, attempts: i32, delay: Duration) -> Result<(), AppError> {
+        let payload = String::from_utf8_lossy(&job.body);
+        match job.spill_id {
+            Some(id) => {
+                self.database
+                    .reschedule_spilled_webhook(id, &payload, attempts, delay.as_secs_f64())
+                    .await?
+            }
+            None => {
+                self.database
+                    .spill_webhook(&payload, &job.context.request_id, attempts, delay.as_secs_f64())
+                    .await?
+            }
+        }
+        self.metrics.spilled.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Removes the spill row of a job that needs no further attempt.
+    async fn release(&self, job: &IngestJob) {
+        let Some(id) = job.spill_id else {
+            return;
+        };
+        if let Err(err) = self.database.delete_spilled_webhook(id).await {
+            warn!("Failed to delete spilled webhook {}, it will be processed again, error: {}", spill_id: id, error: err.to_string());
+        }
+    }
+
+    /// Exponential backoff: `base * 2^(attempts - 1)`, capped at `max_backoff`.
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+        self.config
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.config.max_backoff)
+    }
+
+    pub fn metrics(&self) -> IngestMetricsResponse {
+        let metrics = &self.metrics;
+        IngestMetricsResponse {
+            queue_depth: self.config.capacity - self.sender.capacity(),
+            queue_capacity: self.config.capacity,
+            workers: self.config.workers,
+            busy_workers: metrics.busy_workers.load(Ordering::Relaxed),
+            accepted: metrics.accepted.load(Ordering::Relaxed),
+            processed: metrics.processed.load(Ordering::Relaxed),
+            failed: metrics.failed.load(Ordering::Relaxed),
+            rejected: metrics.rejected.load(Ordering::Relaxed),
+            spilled: metrics.spilled.load(Ordering::Relaxed),
+            max_queue_wait_ms: metrics.max_queue_wait_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Starts the worker pool and the task that feeds spilled webhooks back
+    /// into the queue.
+    pub fn spawn_workers(&self, state: SharedState) -> Vec<JoinHandle<()>> {
+        info!("Starting {} ingest workers, queue capacity: {}", workers: self.config.workers, capacity: self.config.capacity);
+
+        let mut handles = (0..self.config.workers)
+            .map(|_| {
+                let ingest = self.clone();
+                let state = state.clone();
+                tokio::spawn(async move { ingest.work(&state).await })
+            })
+            .collect::<Vec<JoinHandle<()>>>();
+
+        let ingest = self.clone();
+        handles.push(tokio::spawn(async move { ingest.drain_spill().await }));
+        handles
+    }
+
+    /// Stops the workers and the spill feeder, then writes the jobs still
+    /// queued back to `webhook_spill` so another instance picks them up.
+    /// Workers get `shutdown_timeout` to finish the job in hand.
+    pub async fn shutdown(&self, handles: Vec<JoinHandle<()>>) {
+        self.closing.send_replace(true);
+        let deadline = tokio::time::Instant::now() + self.config.shutdown_timeout;
+        for handle in handles {
+            if tokio::time::timeout_at(deadline, handle).await.is_err() {
+                warn!("Ingest worker still busy after the shutdown timeout", );
+            }
+        }
+
+        let mut receiver = self.receiver.lock().await;
+        receiver.close();
+        let mut spilled = 0;
+        while let Ok(job) = receiver.try_recv() {
+            match self.spill(&job, job.attempts, Duration::ZERO).await {
+                Ok(()) => spilled += 1,
+                Err(err) => {
+                    error!("Webhook dropped on shutdown, request ID {}, error: {}", request_id: job.context.request_id, error: err.to_string());
+                }
+            }
+        }
+        info!("Ingest stopped, {} queued webhooks spilled", spilled: spilled);
+    }
+
+    async fn work(&self, state: &SharedState) {
+        let mut closing = self.closing.subscribe();
+        loop {
+            let job = tokio::select! {
+                job = async { self.receiver.lock().await.recv().await } => job,
+                _ = closing.wait_for(|closing| *closing) => return,
+            };
+            let Some(job) = job else {
+                return;
+            };
+
+            let waited = job.queued_at.elapsed().as_millis() as u64;
+            self.metrics.max_queue_wait_ms.fetch_max(waited, Ordering::Relaxed);
+            self.metrics.busy_workers.fetch_add(1, Ordering::Relaxed);
+            let result = process_webhook(state, &job.context, &job.envelope).await;
+            self.metrics.busy_workers.fetch_sub(1, Ordering::Relaxed);
+
+            match result {
+                Ok(failed) if failed.is_empty() => {
+                    self.metrics.processed.fetch_add(1, Ordering::Relaxed);
+                    self.release(&job).await;
+                }
+                Ok(failed) => {
+                    self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                    let error = format!("{} of {} entries failed", failed.len(), job.envelope.entry.len());
+                    self.retry(job.with_entries(failed), error).await;
+                }
+                Err(err) => {
+                    self.metrics.failed.fetch_add(1, Ordering::Relaxed);
+                    self.retry(job, err.to_string()).await;
+                }
+            }
+        }
+    }
+
+    async fn retry(&self, job: IngestJob, error: String) {
+        let attempts = job.attempts + 1;
+        let quarantined = attempts >= self.config.max_attempts;
+        let result = if quarantined {
+            error!("Webhook quarantined after {} attempts, request ID {}, error: {}",
+                attempts: attempts,
+                request_id: job.context.request_id,
+                error: error);
+            self.database
+                .quarantine_webhook(
+                    Some(&job.envelope.object),
+                    &String::from_utf8_lossy(&job.body),
+                    &error,
+                    &job.context.request_id,
+                )
+                .await
+        } else {
+            warn!("Webhook processing failed, attempt {}, request ID {}, error: {}",
+                attempts: attempts,
+                request_id: job.context.request_id,
+                error: error);
+            self.spill(&job, attempts, self.backoff(attempts)).await
+        };
+
+        match result {
+            Ok(()) if quarantined => self.release(&job).await,
+            Ok(()) => {}
+            Err(err) if job.spill_id.is_some() => {
+                warn!("Failed to reschedule webhook, retrying once its lease runs out, request ID {}, error: {}",
+                    request_id: job.context.request_id,
+                    error: err.to_string());
+            }
+            Err(err) => {
+                error!("Webhook dropped, request ID {}, error: {}", request_id: job.context.request_id, error: err.to_string());
+            }
+        }
+    }
+
+    async fn drain_spill(&self) {
+        let mut closing = self.closing.subscribe();
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(self.config.spill_poll_interval) => {}
+                _ = closing.wait_for(|closing| *closing) => return,
+            }
+
+            let room = self.sender.capacity();
+            if room == 0 {
+                continue;
+            }
+            let spilled = match self
+                .database
+                .claim_spilled_webhooks(room as i64, self.config.spill_lease.as_secs_f64())
+                .await
+            {
+                Ok(spilled) => spilled,
+                Err(err) => {
+                    error!("Failed to claim spilled webhooks, error: {}", error: err.to_string());
+                    continue;
+                }
+            };
+
+            for spilled in spilled {
+                let envelope = match serde_json::from_str::<WebhookEnvelope>(&spilled.payload) {
+                    Ok(envelope) => envelope,
+                    Err(err) => {
+                        warn!("Quarantining unreadable spilled webhook {}, error: {}", spill_id: spilled.id, error: err.to_string());
+                        let result = match self
+                            .database
+                            .quarantine_webhook(None, &spilled.payload, &err.to_string(), &spilled.trace_id)
+                            .await
+                        {
+                            Ok(()) => self.database.delete_spilled_webhook(spilled.id).await,
+                            Err(err) => Err(err),
+                        };
+                        if let Err(err) = result {
+                            error!("Spilled webhook {} dropped, error: {}", spill_id: spilled.id, error: err.to_string());
+                        }
+                        continue;
+                    }
+                };
+                let context = RequestContext {
+                    uri: Uri::default(),
+                    request_id: spilled.trace_id,
+                };
+                let job = IngestJob {
+                    attempts: spilled.attempts,
+                    spill_id: Some(spilled.id),
+                    ..IngestJob::new(context, Bytes::from(spilled.payload), envelope)
+                };
+                tokio::select! {
+                    sent = self.sender.send(job) => {
+                        if sent.is_err() {
+                            return;
+                        }
+                    }
+                    // the rest stay leased and are claimed again once it runs out
+                    _ = closing.wait_for(|closing| *closing) => return,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use emit::{__emit_get_event_data, emit, info, warn};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::{cache::CacheService, database::Database, errors::AppError, handlers::auth::Authenticator, utils::config::env_or};
+
+/// Which per-instance cache an [`InvalidationMessage`] applies to.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidationScope {
+    #[default]
+    Eligibility,
+    ApiKeys,
+}
+
+/// Published on the control channel. `ref_id: None` clears every entry.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvalidationMessage {
+    pub origin: String,
+    #[serde(default)]
+    pub scope: InvalidationScope,
+    pub ref_id: Option<String>,
+}
+
+/// Keeps the per-instance eligibility and API key caches of every replica in step. Changes
+/// are applied locally, then broadcast over Redis Pub/Sub to the other
+/// instances, which apply them from [`Invalidator::spawn_listener`].
+#[derive(Clone)]
+pub struct Invalidator {
+    database: Database,
+    cache: CacheService,
+    auth: Authenticator,
+    channel: String,
+    origin: String,
+}
+
+impl Invalidator {
+    pub fn from_env(database: Database, cache: CacheService, auth: Authenticator) -> Self {
+        Invalidator {
+            database,
+            cache,
+            auth,
+            channel: env_or("CACHE_INVALIDATION_CHANNEL", "femto:invalidate".to_string()),
+            origin: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub async fn invalidate(&self, ref_id: &str) {
+        self.database.remove_eligible(ref_id).await;
+        self.broadcast(InvalidationScope::Eligibility, Some(ref_id.to_string())).await
+    }
+
+    pub async fn invalidate_all(&self) {
+        self.database.flush_eligible().await;
+        self.broadcast(InvalidationScope::Eligibility, None).await
+    }
+
+    /// Forgets cached API key lookups on every instance, e.g. after a key is
+    /// revoked.
+    pub async fn invalidate_api_keys(&self) {
+        self.auth.invalidate_keys();
+        self.broadcast(InvalidationScope::ApiKeys, None).await
+    }
+
+    /// Publishes a change that was already applied locally. Callers run this
+    /// after their database commit, so a failed publish is only logged: the
+    /// other instances catch up when their cache entries expire or their
+    /// listener reconnects.
+    async fn broadcast(&self, scope: InvalidationScope, ref_id: Option<String>) {
+        let message = InvalidationMessage {
+            origin: self.origin.clone(),
+            scope,
+            ref_id,
+        };
+        let result = match serde_json::to_string(&message) {
+            Ok(payload) => self.cache.publish(self.channel.clone(), payload).await,
+            Err(err) => Err(AppError::InternalServerError(err.to_string())),
+        };
+
+        if let Err(err) = result {
+            warn!("Failed to broadcast cache invalidation, error: {}", error: err.to_string());
+        }
+    }
+
+    async fn apply(&self, message: InvalidationMessage) {
+        if message.origin == self.origin {
+            return;
+        }
+
+        match (message.scope, message.ref_id) {
+            (InvalidationScope::ApiKeys, _) => {
+                info!("Invalidating cached API keys",);
+                self.auth.invalidate_keys();
+            }
+            (InvalidationScope::Eligibility, Some(ref_id)) => {
+                info!("Invalidating eligibility of page {}", page_id: ref_id);
+                self.database.remove_eligible(&ref_id).await;
+            }
+            (InvalidationScope::Eligibility, None) => {
+                info!("Invalidating all eligibility entries",);
+                self.database.flush_eligible().await;
+            }
+        }
+    }
+
+    pub fn spawn_listener(&self) -> JoinHandle<()> {
+        let invalidator = self.clone();
+        tokio::spawn(async move { invalidator.run().await })
+    }
+
+    async fn run(&self) {
+        loop {
+            if let Err(err) = self.listen().await {
+                warn!("Invalidation listener disconnected, error: {}", error: err.to_string());
+            }
+
+            // Messages published while unsubscribed are lost, so start over
+            // from empty caches.
+            self.database.flush_eligible().await;
+            self.auth.invalidate_keys();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn listen(&self) -> Result<(), AppError> {
+        let mut pubsub = self.cache.subscribe(&self.channel).await?;
+        info!("Listening for cache invalidations on {}", channel: self.channel);
+
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let payload: String = msg.get_payload()?;
+            match serde_json::from_str::<InvalidationMessage>(&payload) {
+                Ok(message) => self.apply(message).await,
+                Err(err) => warn!("Ignoring invalid invalidation message, error: {}", error: err.to_string()),
+            }
+        }
+
+        Ok(())
+    }
+}
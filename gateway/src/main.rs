@@ -1,7 +1,17 @@
 use axum::ServiceExt;
 use axum::extract::Request;
+use alerts::PaymentAlerts;
+use archive::Replayer;
 use cache::CacheService;
 use database::Database;
+use dedupe::Deduplicator;
+use handlers::{auth::Authenticator, state::SharedState};
+use ingest::{Ingest, IngestConfig};
+use invalidation::Invalidator;
+use routing::{DisabledAppPolicy, ForwardMode};
+use outbox::{Outbox, OutboxConfig};
+use send::{SendConfig, Sender};
+use sinks::MessageSinks;
 use dotenv::dotenv;
 use emit::{__emit_get_event_data, emit, info};
 use emit::{
@@ -12,7 +22,7 @@ use std::sync::Mutex;
 use tokio::net::TcpListener;
 use tower_http::normalize_path::NormalizePathLayer;
 use tower_layer::Layer;
-use utils::emit_seq::SeqCollector;
+use utils::{emit_seq::SeqCollector, token_cipher::TokenCipher};
 
 use crate::handlers::router;
 
@@ -22,13 +32,22 @@ extern crate slog_term;
 extern crate slog_async;
 extern crate slog_gelf;
 
-use slog::{o, Drain, Logger};
+use slog::{o, Drain};
 
+mod alerts;
+mod archive;
 mod cache;
 mod database;
+mod dedupe;
 mod errors;
 mod handlers;
+mod ingest;
+mod invalidation;
 mod models;
+mod outbox;
+mod routing;
+mod send;
+mod sinks;
 mod utils;
 
 #[tokio::main]
@@ -53,7 +72,7 @@ async fn main() -> io::Result<()> {
     let decorator = slog_term::TermDecorator::new().build();
 
     let term = slog_term::FullFormat::new(decorator).build().fuse();
-    let gelf = slog_gelf::Gelf::new(&*hostname.into_string().unwrap(), &*gelf_address)?
+    let gelf = slog_gelf::Gelf::new(&hostname.into_string().unwrap(), &gelf_address)?
         .fuse();
 
     let drains = Mutex::new(slog::Duplicate::new(term, gelf)).fuse();
@@ -62,12 +81,67 @@ async fn main() -> io::Result<()> {
         .build()
         .fuse();
     let logger = slog::Logger::root(drains, o!("key" => "value"));
-    let database = Database::init().await;
-    let cache = CacheService::init().await;
-
-    let result = run(database, cache, logger).await;
-    if result.is_err() {
-        log::error!("{}", result.unwrap_err().to_string());
+    let database = match Database::init().await {
+        Ok(database) => database,
+        Err(err) => {
+            log::error!("Unable to connect to database: {}", err.to_string());
+            std::process::exit(1)
+        }
+    };
+    let cache = match CacheService::init().await {
+        Ok(cache) => cache,
+        Err(err) => {
+            log::error!("Unable to init redis client: {}", err.to_string());
+            std::process::exit(1)
+        }
+    };
+    let sinks = MessageSinks::init(&cache).await;
+    let outbox = Outbox::new(database.clone(), sinks.clone(), OutboxConfig::from_env());
+    outbox.spawn_worker();
+    let dedupe = Deduplicator::from_env(cache.clone());
+    let auth = Authenticator::from_env(database.clone());
+    let invalidator = Invalidator::from_env(database.clone(), cache.clone(), auth.clone());
+    invalidator.spawn_listener();
+    let disabled_app_policy = DisabledAppPolicy::from_env();
+    let forward_mode = ForwardMode::from_env();
+    let alerts = PaymentAlerts::from_env(database.clone(), sinks.clone());
+    alerts.spawn_sweeper();
+    let replayer = Replayer::from_env(database.clone(), outbox.clone(), forward_mode.clone());
+    let ingest = Ingest::new(database.clone(), IngestConfig::from_env());
+    let cipher = match TokenCipher::from_env() {
+        Ok(cipher) => cipher,
+        Err(err) => {
+            log::error!("Invalid page token encryption config: {}", err.to_string());
+            std::process::exit(1)
+        }
+    };
+    let sender = Sender::new(database.clone(), cache.clone(), cipher.clone(), SendConfig::from_env());
+    sender.spawn_listener();
+
+    let state = SharedState {
+        database,
+        cache,
+        sinks,
+        outbox,
+        dedupe,
+        auth,
+        invalidator,
+        disabled_app_policy,
+        forward_mode,
+        sender,
+        cipher,
+        alerts,
+        replayer,
+        ingest,
+        logger,
+    };
+    let ingest = state.ingest.clone();
+    let workers = ingest.spawn_workers(state.clone());
+
+    let result = run(state).await;
+    ingest.shutdown(workers).await;
+    if let Err(err) = result {
+        log::error!("{}", err.to_string());
         std::process::exit(1)
     } else {
       info!("Successfully start server !", );
@@ -76,7 +150,8 @@ async fn main() -> io::Result<()> {
     Ok(())
 }
 
-pub async fn run(database: Database, cache: CacheService, logger: Logger) -> Result<(), Box<dyn Error>> {
+pub async fn run(state: SharedState) -> Result<(), Box<dyn Error>> {
+    let logger = state.logger.clone();
     let app_environment = env::var("APP_ENVIRONMENT").unwrap_or("development".to_string());
     let app_host = env::var("APP_HOST").unwrap_or("0.0.0.0".to_string());
     let app_port = env::var("APP_PORT").unwrap_or("3000".to_string());
@@ -104,12 +179,45 @@ pub async fn run(database: Database, cache: CacheService, logger: Logger) -> Res
     );
 
 
-    let app =  NormalizePathLayer::trim_trailing_slash().layer(router(database, cache.clone(), logger));
+    let app =  NormalizePathLayer::trim_trailing_slash().layer(router(state));
     let app = ServiceExt::<Request>::into_make_service(app);
     axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
         .await
         .unwrap();
 
     Ok(())
 }
 
+/// Resolves on Ctrl+C or SIGTERM, letting in-flight requests finish before
+/// the ingest queue is drained.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error!("Failed to listen for Ctrl+C: {}", err.to_string());
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(err) => {
+                log::error!("Failed to listen for SIGTERM: {}", err.to_string());
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    info!("Shutdown signal received, draining", );
+}
+
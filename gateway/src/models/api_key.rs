@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::handlers::auth::scopes;
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct ApiKey {
+  pub id: i32,
+  pub name: String,
+  pub scopes: Vec<String>,
+  pub enabled: bool,
+  pub app_id: Option<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+  pub id: i32,
+  pub name: String,
+  pub scopes: Vec<String>,
+  pub enabled: bool,
+  pub app_id: Option<String>,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+  fn from(key: ApiKey) -> Self {
+    Self {
+      id: key.id,
+      name: key.name,
+      scopes: key.scopes,
+      enabled: key.enabled,
+      app_id: key.app_id,
+    }
+  }
+}
+
+/// Returned once on creation; the plaintext key cannot be retrieved again.
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+  pub id: i32,
+  pub name: String,
+  pub scopes: Vec<String>,
+  pub app_id: Option<String>,
+  pub key: String,
+}
+
+fn validate_scopes(values: &[String]) -> Result<(), ValidationError> {
+  if values.iter().all(|scope| scopes::ALL.contains(&scope.as_str())) {
+    Ok(())
+  } else {
+    Err(ValidationError::new("scopes"))
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateApiKeyRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub name: String,
+  #[validate(length(min = 1), custom(function = "validate_scopes"))]
+  pub scopes: Vec<String>,
+  /// Application the key acts for, required to `/send` without `admin`.
+  #[validate(length(min = 1, max = 255))]
+  pub app_id: Option<String>,
+}
@@ -1,19 +1,28 @@
 use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
 
+use crate::sinks::SinkKind;
+
+#[derive(sqlx::FromRow)]
 pub struct Application {
   pub app_id: String,
   pub app_name: String,
   pub topic: String,
   pub enabled: bool,
+  pub sink_type: String,
+  pub payment_topic: Option<String>,
 }
 
+#[allow(dead_code)]
 impl Application {
-  pub fn new(app_id: String, app_name: String, topic: String, enabled:bool) -> Self {
+  pub fn new(app_id: String, app_name: String, topic: String, enabled:bool, sink_type: String) -> Self {
     Self {
-      app_id: app_id,
-      app_name: app_name,
-      topic: topic,
-      enabled: enabled
+      app_id,
+      app_name,
+      topic,
+      enabled,
+      sink_type,
+      payment_topic: None
     }
   }
 }
@@ -24,6 +33,8 @@ pub struct ApplicationResponse {
   pub app_name: String,
   pub topic: String,
   pub enabled: bool,
+  pub sink_type: String,
+  pub payment_topic: Option<String>,
 }
 
 impl From<Application> for ApplicationResponse {
@@ -32,7 +43,91 @@ impl From<Application> for ApplicationResponse {
       app_id: app.app_id,
       app_name: app.app_name,
       topic: app.topic,
-      enabled: app.enabled
+      enabled: app.enabled,
+      sink_type: app.sink_type,
+      payment_topic: app.payment_topic
+    }
+  }
+}
+
+fn default_enabled() -> bool {
+  true
+}
+
+fn default_sink_type() -> String {
+  SinkKind::RedisPubsub.to_string()
+}
+
+pub fn validate_sink_type(sink_type: &str) -> Result<(), ValidationError> {
+  sink_type
+    .parse::<SinkKind>()
+    .map(|_| ())
+    .map_err(|_| ValidationError::new("sink_type"))
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateApplicationRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub app_id: String,
+  #[validate(length(min = 1, max = 255))]
+  pub app_name: String,
+  #[validate(length(min = 1, max = 255))]
+  pub topic: String,
+  #[serde(default = "default_enabled")]
+  pub enabled: bool,
+  #[serde(default = "default_sink_type")]
+  #[validate(custom(function = "validate_sink_type"))]
+  pub sink_type: String,
+  #[validate(length(min = 1))]
+  pub app_secret: Option<String>,
+  /// Receives normalized payment events instead of the raw payment changes.
+  #[validate(length(max = 255))]
+  pub payment_topic: Option<String>,
+}
+
+/// Full replacement of an application. `app_secret` is only changed when present.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateApplicationRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub app_name: String,
+  #[validate(length(min = 1, max = 255))]
+  pub topic: String,
+  pub enabled: bool,
+  #[validate(custom(function = "validate_sink_type"))]
+  pub sink_type: String,
+  #[validate(length(min = 1))]
+  pub app_secret: Option<String>,
+  /// Receives normalized payment events instead of the raw payment changes.
+  #[validate(length(max = 255))]
+  pub payment_topic: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Validate)]
+pub struct PatchApplicationRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub app_name: Option<String>,
+  #[validate(length(min = 1, max = 255))]
+  pub topic: Option<String>,
+  pub enabled: Option<bool>,
+  #[validate(custom(function = "validate_sink_type"))]
+  pub sink_type: Option<String>,
+  #[validate(length(min = 1))]
+  pub app_secret: Option<String>,
+  /// Receives normalized payment events instead of the raw payment changes.
+  #[validate(length(max = 255))]
+  pub payment_topic: Option<String>,
+}
+
+impl From<UpdateApplicationRequest> for PatchApplicationRequest {
+  fn from(req: UpdateApplicationRequest) -> Self {
+    Self {
+      app_name: Some(req.app_name),
+      topic: Some(req.topic),
+      enabled: Some(req.enabled),
+      sink_type: Some(req.sink_type),
+      app_secret: req.app_secret,
+      // a full replacement without a payment topic clears it
+      payment_topic: Some(req.payment_topic.unwrap_or_default())
     }
   }
-}
\ No newline at end of file
+}
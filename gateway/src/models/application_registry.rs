@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use validator::{Validate, ValidationError};
+
+use crate::models::messenger_webhook::{EventKind, Messaging, WebhookEntry};
+
+/// Link between a merchant channel (by `ref_id`) and an application (by `app_id`).
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ApplicationRegistry {
+  pub ref_id: String,
+  pub app_id: String,
+  pub filter: Option<Json<RegistryFilter>>,
+}
+
+fn validate_messaging_kinds(kinds: &[String]) -> Result<(), ValidationError> {
+  if kinds.iter().all(|kind| EventKind::is_filter_name(kind)) {
+    Ok(())
+  } else {
+    Err(ValidationError::new("messaging"))
+  }
+}
+
+/// Narrows what a single application receives from a page. Unset lists let
+/// everything through.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, Validate)]
+pub struct RegistryFilter {
+  /// Messaging event kinds to forward, e.g. `message` or `postback`.
+  #[validate(custom(function = "validate_messaging_kinds"))]
+  pub messaging: Option<Vec<String>>,
+  /// `changes[].field` values to forward, e.g. `payment`.
+  pub changes: Option<Vec<String>>,
+  /// Senders whose messaging events are never forwarded.
+  #[serde(default)]
+  pub exclude_senders: Vec<String>,
+}
+
+impl RegistryFilter {
+  fn accepts_messaging(&self, messaging: &Messaging) -> bool {
+    let kind_allowed = self
+      .messaging
+      .as_ref()
+      .is_none_or(|kinds| kinds.iter().any(|kind| kind == messaging.kind()));
+
+    kind_allowed && !messaging.sender.id.as_ref().is_some_and(|id| self.exclude_senders.contains(id))
+  }
+
+  /// Returns the part of `entry` this filter lets through.
+  pub fn apply(&self, entry: &WebhookEntry) -> WebhookEntry {
+    let mut entry = entry.clone();
+    if let Some(messaging) = entry.messaging.as_mut() {
+      messaging.retain(|m| self.accepts_messaging(m));
+    }
+    if let (Some(changes), Some(fields)) = (entry.changes.as_mut(), &self.changes) {
+      changes.retain(|c| fields.contains(&c.field));
+    }
+    entry
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateApplicationRegistryRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub ref_id: String,
+  #[validate(length(min = 1, max = 255))]
+  pub app_id: String,
+  #[validate(nested)]
+  pub filter: Option<RegistryFilter>,
+}
+
+/// Replaces every application registered to a channel. Filters of the
+/// applications that stay registered are kept.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateApplicationRegistryRequest {
+  #[validate(length(max = 100))]
+  pub app_ids: Vec<String>,
+}
+
+/// Sets or clears the filter of one registration.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct PatchApplicationRegistryRequest {
+  #[validate(nested)]
+  pub filter: Option<RegistryFilter>,
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+/// A webhook entry about to be archived, with the original JSON bytes.
+#[derive(Clone, Debug)]
+pub struct NewArchivedEntry {
+    pub received_at: DateTime<Utc>,
+    pub object: String,
+    pub page_id: String,
+    pub event_types: Vec<String>,
+    pub request_id: String,
+    pub payload: String,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct ArchivedEntry {
+    pub id: i64,
+    pub received_at: DateTime<Utc>,
+    pub object: String,
+    pub page_id: String,
+    pub event_types: Vec<String>,
+    pub request_id: String,
+    pub payload: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedEntrySummary {
+    pub id: i64,
+    pub received_at: DateTime<Utc>,
+    pub page_id: String,
+    pub event_types: Vec<String>,
+    pub request_id: String,
+}
+
+impl From<&ArchivedEntry> for ArchivedEntrySummary {
+    fn from(entry: &ArchivedEntry) -> Self {
+        Self {
+            id: entry.id,
+            received_at: entry.received_at,
+            page_id: entry.page_id.clone(),
+            event_types: entry.event_types.clone(),
+            request_id: entry.request_id.clone(),
+        }
+    }
+}
+
+/// Where replayed entries of an application are published.
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct ReplayTarget {
+    pub id: i32,
+    pub topic: String,
+    pub sink_type: String,
+    pub enabled: bool,
+}
+
+fn default_replay_limit() -> u32 {
+    1_000
+}
+
+fn validate_replay_window(req: &ReplayRequest) -> Result<(), ValidationError> {
+    if req.from < req.to {
+        Ok(())
+    } else {
+        Err(ValidationError::new("replay_window"))
+    }
+}
+
+/// Archived entries of the channels `app_id` is registered to, received in
+/// `[from, to)`, optionally narrowed to one page and some event types.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+#[validate(schema(function = "validate_replay_window"))]
+pub struct ReplayRequest {
+    #[validate(length(min = 1, max = 255))]
+    pub app_id: String,
+    #[validate(length(min = 1, max = 255))]
+    pub page_id: Option<String>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub event_types: Option<Vec<String>>,
+    /// Only report what would be replayed.
+    #[serde(default)]
+    pub dry_run: bool,
+    #[serde(default = "default_replay_limit")]
+    #[validate(range(min = 1, max = 10_000))]
+    pub limit: u32,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayResponse {
+    pub matched: u64,
+    pub dry_run: bool,
+    /// The first matched entries, on dry runs.
+    pub entries: Option<Vec<ArchivedEntrySummary>>,
+}
@@ -8,8 +8,8 @@ pub struct HealthCheck {
 impl HealthCheck {
   pub fn new(date_now: String, ping: String) -> Self {
     Self {
-      date_now: date_now,
-      ping: ping,
+      date_now,
+      ping,
     }
   }
 }
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct SpilledWebhook {
+    pub id: i64,
+    pub payload: String,
+    pub trace_id: String,
+    pub attempts: i32,
+}
+
+/// Counters since start, plus the current queue depth and busy workers.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IngestMetricsResponse {
+    pub queue_depth: usize,
+    pub queue_capacity: usize,
+    pub workers: usize,
+    pub busy_workers: u64,
+    pub accepted: u64,
+    pub processed: u64,
+    pub failed: u64,
+    pub rejected: u64,
+    pub spilled: u64,
+    pub max_queue_wait_ms: u64,
+}
@@ -1,40 +1,92 @@
+use std::fmt;
+
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
-pub struct MerchantChannel {
-  pub id: i32,
-  pub ref_id: String,
-  pub name: String,
-  pub ref_type: String,
+/// Kinds of channel a `merchant_channel.ref_type` can name, and the webhook
+/// `object` each one receives.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChannelType {
+  /// Facebook page, `ref_id` is the page id.
+  Page,
+  /// Instagram professional account, `ref_id` is the IG account id.
+  Instagram,
+  /// WhatsApp Business number, `ref_id` is the phone number id.
+  WhatsApp,
+}
+
+impl ChannelType {
+  pub const ALL: [ChannelType; 3] = [ChannelType::Page, ChannelType::Instagram, ChannelType::WhatsApp];
+
+  pub fn from_object(object: &str) -> Option<ChannelType> {
+    match object {
+      "page" => Some(ChannelType::Page),
+      "instagram" => Some(ChannelType::Instagram),
+      "whatsapp_business_account" => Some(ChannelType::WhatsApp),
+      _ => None,
+    }
+  }
+
+  /// Value stored in `merchant_channel.ref_type`.
+  pub fn ref_type(&self) -> &'static str {
+    match self {
+      ChannelType::Page => "page",
+      ChannelType::Instagram => "instagram",
+      ChannelType::WhatsApp => "whatsapp",
+    }
+  }
+}
+
+/// A page access token as stored in `merchant_channel`. `token` is ciphertext
+/// unless `token_key_id` is null; see [`TokenCipher`] for the scheme.
+#[derive(Clone, Default, sqlx::FromRow)]
+pub struct EncryptedToken {
   pub token: String,
+  pub token_key_id: Option<String>,
+  pub token_dek: Option<String>,
 }
 
-impl MerchantChannel {
-  pub fn new(id: i32, ref_id: String, name: String, ref_type:String, token: String) -> Self {
+impl EncryptedToken {
+  pub fn plaintext(token: &str) -> Self {
     Self {
-      id: id,
-      ref_id: ref_id,
-      name: name,
-      ref_type: ref_type,
-      token: token
+      token: token.to_string(),
+      token_key_id: None,
+      token_dek: None,
     }
   }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MerchantChannelResponse {
+// never print the token, a row without a key id holds it in plaintext
+impl fmt::Debug for EncryptedToken {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("EncryptedToken")
+      .field("token_key_id", &self.token_key_id)
+      .finish_non_exhaustive()
+  }
+}
+
+#[derive(sqlx::FromRow)]
+pub struct MerchantChannel {
   pub id: i32,
   pub ref_id: String,
   pub name: String,
   pub ref_type: String,
+  #[sqlx(flatten)]
+  pub token: EncryptedToken,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct MerchantChannelWithTokenResponse {
+pub struct MerchantChannelResponse {
   pub id: i32,
   pub ref_id: String,
   pub name: String,
   pub ref_type: String,
-  pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotateTokensResponse {
+  pub rotated: u64,
+  pub failed: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -54,14 +106,45 @@ impl From<MerchantChannel> for MerchantChannelResponse {
   }
 }
 
-impl From<MerchantChannel> for MerchantChannelWithTokenResponse {
-  fn from(c: MerchantChannel) -> Self {
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct CreateMerchantChannelRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub ref_id: String,
+  #[validate(length(min = 1, max = 255))]
+  pub name: String,
+  #[validate(length(min = 1, max = 64))]
+  pub ref_type: String,
+  #[validate(length(min = 1))]
+  pub token: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateMerchantChannelRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub name: String,
+  #[validate(length(min = 1, max = 64))]
+  pub ref_type: String,
+  #[validate(length(min = 1))]
+  pub token: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Validate)]
+pub struct PatchMerchantChannelRequest {
+  #[validate(length(min = 1, max = 255))]
+  pub name: Option<String>,
+  #[validate(length(min = 1, max = 64))]
+  pub ref_type: Option<String>,
+  #[validate(length(min = 1))]
+  pub token: Option<String>,
+}
+
+impl From<UpdateMerchantChannelRequest> for PatchMerchantChannelRequest {
+  fn from(req: UpdateMerchantChannelRequest) -> Self {
     Self {
-      id: c.id,
-      ref_id: c.ref_id,
-      name: c.name,
-      ref_type: c.ref_type,
-      token: c.token,
+      name: Some(req.name),
+      ref_type: Some(req.ref_type),
+      token: Some(req.token)
     }
   }
-}
\ No newline at end of file
+}
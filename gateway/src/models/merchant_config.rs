@@ -1,64 +1,147 @@
 use lapin::types::Boolean;
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
 
-#[derive(Debug, Serialize, Deserialize)]
+use std::collections::BTreeMap;
+
+use crate::models::{
+    application_registry::RegistryFilter, merchant_channel::EncryptedToken, messenger_webhook::WebhookEntry,
+    payment_event::PaymentEvent, subscription::Subscription,
+};
+
+/// One application a page routes to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MerchantConfig {
     pub channel_id: i32,
     pub app_id: i32,
     pub topic: String,
     pub enabled: Boolean,
-    pub token: String,
+    /// Decrypted only where it is used, never serialized.
+    #[serde(skip)]
+    pub token: EncryptedToken,
+    pub sink_type: String,
+    pub payment_topic: Option<String>,
+    pub filter: Option<RegistryFilter>,
+    pub subscriptions: Vec<Subscription>,
 }
 
 impl MerchantConfig {
-    pub fn new(channel_id: i32, app_id: i32, topic: String, enabled:Boolean, token: String) -> Self {
-        Self {
-            channel_id,
-            app_id,
-            topic,
-            enabled,
-            token
+    fn topic_for(&self, event_type: &str) -> Option<&str> {
+        if self.subscriptions.is_empty() {
+            return Some(&self.topic);
         }
+
+        self.subscriptions
+            .iter()
+            .find(|s| s.event_type == event_type)
+            .map(|s| s.topic.as_deref().unwrap_or(&self.topic))
     }
-}
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MerchantConfigResponse {
-    pub channel_id: i32,
-    pub app_id: i32,
-    pub topic: String,
-    pub enabled: Boolean,
+    /// Removes the payment and invoice changes the application is subscribed
+    /// to from `entry` when it has a payment topic, so they can be published
+    /// there as [`PaymentEvent`]s.
+    pub fn take_payments(&self, entry: &mut WebhookEntry) -> Vec<PaymentEvent> {
+        let (Some(_), Some(changes)) = (&self.payment_topic, entry.changes.as_mut()) else {
+            return Vec::new();
+        };
+
+        let mut payments = Vec::new();
+        changes.retain(|change| match PaymentEvent::from_change(change) {
+            Some(payment) if self.topic_for(change.event_type()).is_some() => {
+                payments.push(payment);
+                false
+            }
+            _ => true,
+        });
+        payments
+    }
+
+    /// Splits `entry` by destination topic, dropping events the application
+    /// is not subscribed to.
+    pub fn route(&self, entry: WebhookEntry) -> Vec<(String, WebhookEntry)> {
+        if self.subscriptions.is_empty() {
+            return vec![(self.topic.clone(), entry)];
+        }
+
+        let mut routed: BTreeMap<String, WebhookEntry> = BTreeMap::new();
+        for messaging in entry.messaging.iter().flatten() {
+            if let Some(topic) = self.topic_for(messaging.event_type()) {
+                routed
+                    .entry(topic.to_string())
+                    .or_insert_with(|| entry.empty_copy())
+                    .messaging
+                    .get_or_insert_with(Vec::new)
+                    .push(messaging.clone());
+            }
+        }
+        for change in entry.changes.iter().flatten() {
+            if let Some(topic) = self.topic_for(change.event_type()) {
+                routed
+                    .entry(topic.to_string())
+                    .or_insert_with(|| entry.empty_copy())
+                    .changes
+                    .get_or_insert_with(Vec::new)
+                    .push(change.clone());
+            }
+        }
+
+        routed.into_iter().collect()
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MerchantConfigWithTokenResponse {
+/// A channel joined to its registered application. The application columns are
+/// null when the channel exists but no application is registered to it.
+#[derive(Debug, sqlx::FromRow)]
+pub struct MerchantConfigRow {
     pub channel_id: i32,
-    pub app_id: i32,
-    pub topic: String,
-    pub enabled: Boolean,
-    pub token: String,
+    pub app_id: Option<i32>,
+    pub topic: Option<String>,
+    pub enabled: Option<Boolean>,
+    #[sqlx(flatten)]
+    pub token: EncryptedToken,
+    pub sink_type: Option<String>,
+    pub payment_topic: Option<String>,
+    pub filter: Option<Json<RegistryFilter>>,
+    pub subscriptions: Json<Vec<Subscription>>,
 }
 
+/// Outcome of resolving a page id, cached as a whole so unknown pages are
+/// answered without a query too.
+#[derive(Clone, Debug)]
+pub enum MerchantLookup {
+    NotEligible,
+    Unconfigured,
+    Configured(Vec<MerchantConfig>),
+}
 
-impl From<MerchantConfig> for MerchantConfigResponse {
-    fn from(c: MerchantConfig) -> Self {
-        Self {
-            channel_id: c.channel_id,
-            app_id: c.app_id,
-            topic: c.topic,
-            enabled: c.enabled,
+impl From<Vec<MerchantConfigRow>> for MerchantLookup {
+    fn from(rows: Vec<MerchantConfigRow>) -> Self {
+        if rows.is_empty() {
+            return MerchantLookup::NotEligible;
         }
-    }
-}
 
-impl From<MerchantConfig> for MerchantConfigWithTokenResponse {
-    fn from(c: MerchantConfig) -> Self {
-        Self {
-            channel_id: c.channel_id,
-            app_id: c.app_id,
-            topic: c.topic,
-            enabled: c.enabled,
-            token: c.token,
+        let configs = rows
+            .into_iter()
+            .filter_map(|row| match (row.app_id, row.topic, row.enabled, row.sink_type) {
+                (Some(app_id), Some(topic), Some(enabled), Some(sink_type)) => Some(MerchantConfig {
+                    channel_id: row.channel_id,
+                    app_id,
+                    topic,
+                    enabled,
+                    token: row.token,
+                    sink_type,
+                    payment_topic: row.payment_topic,
+                    subscriptions: row.subscriptions.0,
+                    filter: row.filter.map(|filter| filter.0),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<MerchantConfig>>();
+
+        if configs.is_empty() {
+            MerchantLookup::Unconfigured
+        } else {
+            MerchantLookup::Configured(configs)
         }
     }
-}
\ No newline at end of file
+}
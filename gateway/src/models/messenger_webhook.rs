@@ -1,5 +1,10 @@
-use serde::{Deserialize, Serialize};
-use serde_json::Number;
+use serde::{
+    de::{self, DeserializeOwned},
+    ser, Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{value::RawValue, Map, Number, Value};
+
+use crate::models::merchant_channel::ChannelType;
 
 #[derive(Serialize, Clone, Deserialize, Debug)]
 pub struct MessengerVerifysubscription {
@@ -11,21 +16,31 @@ pub struct MessengerVerifysubscription {
     pub hub_challenge: Option<String>,
 }
 
+/// The user or page behind an event. Checkbox plugin optins carry a
+/// `user_ref` instead of an `id`.
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Default, Deserialize)]
 pub struct Sender {
-    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Serialize, Default, Deserialize)]
 pub struct Receipient {
-    pub id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Default, Deserialize)]
 pub struct QuickReplyPayload {
     pub payload: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
@@ -39,8 +54,72 @@ impl QuickReplyPayload {
 #[serde_with::skip_serializing_none]
 #[derive(Debug, Clone, Serialize, Default, Deserialize)]
 pub struct Message {
+    pub mid: Option<String>,
     pub text: Option<String>,
     pub quick_reply: Option<QuickReplyPayload>,
+    /// Set on messages the page itself sent, echoed back by Meta.
+    pub is_echo: Option<bool>,
+    /// App that sent an echoed message.
+    pub app_id: Option<Number>,
+    pub metadata: Option<String>,
+    pub reply_to: Option<ReplyTo>,
+    pub attachments: Option<Vec<Attachment>>,
+    pub referral: Option<Referral>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Default, Deserialize)]
+pub struct ReplyTo {
+    pub mid: Option<String>,
+    pub is_self_reply: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentType {
+    Image,
+    Video,
+    Audio,
+    File,
+    Template,
+    Fallback,
+    /// Any type Meta adds later, kept verbatim.
+    #[serde(untagged)]
+    Other(String),
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    #[serde(rename = "type")]
+    pub attachment_type: AttachmentType,
+    pub payload: Option<AttachmentPayload>,
+    /// Only sent with `fallback` attachments.
+    pub title: Option<String>,
+    /// Only sent with `fallback` attachments.
+    pub url: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Media attachments carry `url`; templates carry `template_type` and their
+/// elements, which are kept in `extra`.
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttachmentPayload {
+    pub url: Option<String>,
+    pub title: Option<String>,
+    pub sticker_id: Option<Number>,
+    pub template_type: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
@@ -52,6 +131,14 @@ impl Message {
     pub fn get_quick_reply(&self) -> Option<QuickReplyPayload> {
         self.quick_reply.clone()
     }
+
+    pub fn is_echo(&self) -> bool {
+        self.is_echo.unwrap_or(false)
+    }
+
+    pub fn attachments(&self) -> &[Attachment] {
+        self.attachments.as_deref().unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Default, Deserialize)]
@@ -69,36 +156,374 @@ impl MessagePostback {
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DeliveryInfo {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub mids: Vec<String>,
     pub watermark: Number,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ReadInfo {
     pub watermark: Number,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AccountLinkingInfo {
     pub status: String,
-    pub authorization_code: String,
+    pub authorization_code: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reaction {
+    pub mid: Option<String>,
+    pub action: Option<String>,
+    pub reaction: Option<String>,
+    pub emoji: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Referral {
+    #[serde(rename = "ref")]
+    pub ref_param: Option<String>,
+    pub source: Option<String>,
+    #[serde(rename = "type")]
+    pub referral_type: Option<String>,
+    pub ad_id: Option<String>,
+    pub referer_uri: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
 #[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Optin {
+    #[serde(rename = "ref")]
+    pub ref_param: Option<String>,
+    #[serde(rename = "type")]
+    pub optin_type: Option<String>,
+    pub payload: Option<String>,
+    pub user_ref: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// One of the handover protocol events; `action` is the key Meta sent it
+/// under, e.g. `pass_thread_control`.
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub struct HandoverProtocol {
+    pub action: String,
+    pub body: Value,
+}
+
+/// The event carried by a messaging item or a change.
+#[derive(Clone, Debug)]
+pub enum MessengerEvent {
+    Message(Message),
+    Postback(MessagePostback),
+    Delivery(DeliveryInfo),
+    Read(ReadInfo),
+    Reaction(Reaction),
+    Referral(Referral),
+    Optin(Optin),
+    AccountLinking(AccountLinkingInfo),
+    HandoverProtocol(HandoverProtocol),
+    PaymentChange(ChangeEventValue),
+    InvoiceChange(ChangeEventValue),
+    /// Not recognised. For messaging the raw fields stay in
+    /// [`Messaging::extra`]; for changes the raw `value` is kept here.
+    Unknown(Option<Value>),
+}
+
+const HANDOVER_KEYS: &[&str] = &[
+    "pass_thread_control",
+    "take_thread_control",
+    "request_thread_control",
+    "app_roles",
+];
+
+impl MessengerEvent {
+    /// Takes the first recognised event out of a messaging item's fields. A
+    /// field that does not parse as its typed event is left in place and the
+    /// event is `Unknown`, so nothing Meta sent is dropped.
+    fn take_from_messaging(fields: &mut Map<String, Value>) -> MessengerEvent {
+        const KEYS: &[&str] = &[
+            "message",
+            "postback",
+            "delivery",
+            "read",
+            "reaction",
+            "referral",
+            "optin",
+            "account_linking",
+        ];
+
+        for key in KEYS.iter().chain(HANDOVER_KEYS) {
+            let Some(value) = fields.remove(*key) else {
+                continue;
+            };
+
+            let event = match *key {
+                "message" => serde_json::from_value(value.clone()).map(MessengerEvent::Message),
+                "postback" => serde_json::from_value(value.clone()).map(MessengerEvent::Postback),
+                "delivery" => serde_json::from_value(value.clone()).map(MessengerEvent::Delivery),
+                "read" => serde_json::from_value(value.clone()).map(MessengerEvent::Read),
+                "reaction" => serde_json::from_value(value.clone()).map(MessengerEvent::Reaction),
+                "referral" => serde_json::from_value(value.clone()).map(MessengerEvent::Referral),
+                "optin" => serde_json::from_value(value.clone()).map(MessengerEvent::Optin),
+                "account_linking" => serde_json::from_value(value.clone()).map(MessengerEvent::AccountLinking),
+                action => Ok(MessengerEvent::HandoverProtocol(HandoverProtocol {
+                    action: action.to_string(),
+                    body: value.clone(),
+                })),
+            };
+
+            match event {
+                Ok(event) => return event,
+                Err(_) => {
+                    fields.insert(key.to_string(), value);
+                    return MessengerEvent::Unknown(None);
+                }
+            }
+        }
+
+        MessengerEvent::Unknown(None)
+    }
+
+    /// The key and body this event is serialized under in a messaging item.
+    fn messaging_field(&self) -> Result<Option<(String, Value)>, serde_json::Error> {
+        let field = match self {
+            MessengerEvent::Message(v) => ("message".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Postback(v) => ("postback".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Delivery(v) => ("delivery".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Read(v) => ("read".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Reaction(v) => ("reaction".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Referral(v) => ("referral".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::Optin(v) => ("optin".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::AccountLinking(v) => ("account_linking".to_string(), serde_json::to_value(v)?),
+            MessengerEvent::HandoverProtocol(v) => (v.action.clone(), v.body.clone()),
+            MessengerEvent::PaymentChange(_) | MessengerEvent::InvoiceChange(_) | MessengerEvent::Unknown(_) => {
+                return Ok(None)
+            }
+        };
+
+        Ok(Some(field))
+    }
+
+    /// Classifies a change `value`. Payment updates win over the invoice they
+    /// belong to.
+    fn from_change_value(value: Option<Value>) -> MessengerEvent {
+        let Some(value) = value else {
+            return MessengerEvent::Unknown(None);
+        };
+
+        let has = |key: &str| value.get(key).is_some_and(|v| !v.is_null());
+        let event = if has("payment") {
+            serde_json::from_value(value.clone()).map(MessengerEvent::PaymentChange)
+        } else if has("invoice_id") {
+            serde_json::from_value(value.clone()).map(MessengerEvent::InvoiceChange)
+        } else {
+            return MessengerEvent::Unknown(Some(value));
+        };
+
+        event.unwrap_or(MessengerEvent::Unknown(Some(value)))
+    }
+
+    fn change_value(&self) -> Result<Option<Value>, serde_json::Error> {
+        match self {
+            MessengerEvent::PaymentChange(v) | MessengerEvent::InvoiceChange(v) => serde_json::to_value(v).map(Some),
+            MessengerEvent::Unknown(v) => Ok(v.clone()),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn take_field<T: DeserializeOwned, E: de::Error>(fields: &mut Map<String, Value>, key: &'static str) -> Result<T, E> {
+    let value = fields.remove(key).ok_or_else(|| E::missing_field(key))?;
+    serde_json::from_value(value).map_err(E::custom)
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
 pub struct Messaging {
     pub sender: Sender,
-    pub postback: Option<MessagePostback>,
-    pub message: Option<Message>,
-    pub delivery: Option<DeliveryInfo>,
-    pub read: Option<ReadInfo>,
-    pub account_linking: Option<AccountLinkingInfo>,
     pub recipient: Receipient,
-    pub reaction: Option<String>,
     pub timestamp: Number,
+    pub event: MessengerEvent,
+    /// Fields this model does not know, forwarded unchanged.
+    pub extra: Map<String, Value>,
+}
+
+impl<'de> Deserialize<'de> for Messaging {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Map::deserialize(deserializer)?;
+        let sender = take_field(&mut fields, "sender")?;
+        let recipient = take_field(&mut fields, "recipient")?;
+        let timestamp = take_field(&mut fields, "timestamp")?;
+        let event = MessengerEvent::take_from_messaging(&mut fields);
+
+        Ok(Messaging {
+            sender,
+            recipient,
+            timestamp,
+            event,
+            extra: fields,
+        })
+    }
+}
+
+impl Serialize for Messaging {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fields = self.extra.clone();
+        fields.insert("sender".to_string(), serde_json::to_value(&self.sender).map_err(ser::Error::custom)?);
+        fields.insert("recipient".to_string(), serde_json::to_value(&self.recipient).map_err(ser::Error::custom)?);
+        fields.insert("timestamp".to_string(), Value::Number(self.timestamp.clone()));
+        if let Some((key, value)) = self.event.messaging_field().map_err(ser::Error::custom)? {
+            fields.insert(key, value);
+        }
+
+        fields.serialize(serializer)
+    }
+}
+
+/// Classification of a messaging event or change. Registry filters and
+/// subscriptions both name events through [`EVENT_NAMES`], so the two
+/// vocabularies cannot drift apart.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    Message,
+    MessageEcho,
+    Postback,
+    Delivery,
+    Read,
+    Reaction,
+    Referral,
+    Optin,
+    AccountLinking,
+    Handover,
+    MessagingOther,
+    Invoice,
+    Payment,
+    ChangesOther,
+}
+
+/// Every [`EventKind`] with its registry filter name, if filters can select
+/// it, and its subscription event type. Echoes are filtered as `message`.
+const EVENT_NAMES: &[(EventKind, Option<&str>, &str)] = &[
+    (EventKind::Message, Some("message"), "messages"),
+    (EventKind::MessageEcho, Some("message"), "message_echoes"),
+    (EventKind::Postback, Some("postback"), "postbacks"),
+    (EventKind::Delivery, Some("delivery"), "deliveries"),
+    (EventKind::Read, Some("read"), "reads"),
+    (EventKind::Reaction, Some("reaction"), "reactions"),
+    (EventKind::Referral, Some("referral"), "referrals"),
+    (EventKind::Optin, Some("optin"), "optins"),
+    (EventKind::AccountLinking, Some("account_linking"), "account_linking"),
+    (EventKind::Handover, Some("handover"), "handover"),
+    (EventKind::MessagingOther, Some("other"), "messaging:other"),
+    (EventKind::Invoice, None, "changes:invoice"),
+    (EventKind::Payment, None, "changes:payment"),
+    (EventKind::ChangesOther, None, "changes:other"),
+];
+
+impl EventKind {
+    fn names(self) -> (Option<&'static str>, &'static str) {
+        EVENT_NAMES
+            .iter()
+            .find(|(kind, _, _)| *kind == self)
+            .map(|(_, filter_name, event_type)| (*filter_name, *event_type))
+            .unwrap_or((None, "changes:other"))
+    }
+
+    /// Name registry filters use for this kind, `None` for changes.
+    pub fn filter_name(self) -> Option<&'static str> {
+        self.names().0
+    }
+
+    /// Subscription event type of this kind.
+    pub fn event_type(self) -> &'static str {
+        self.names().1
+    }
+
+    pub fn is_filter_name(name: &str) -> bool {
+        EVENT_NAMES.iter().any(|(_, filter_name, _)| *filter_name == Some(name))
+    }
+
+    pub fn is_event_type(name: &str) -> bool {
+        EVENT_NAMES.iter().any(|(_, _, event_type)| *event_type == name)
+    }
+}
+
+impl Messaging {
+    /// Classifies the event this messaging item carries.
+    pub fn event_kind(&self) -> EventKind {
+        match &self.event {
+            MessengerEvent::Message(message) if message.is_echo() => EventKind::MessageEcho,
+            MessengerEvent::Message(_) => EventKind::Message,
+            MessengerEvent::Postback(_) => EventKind::Postback,
+            MessengerEvent::Delivery(_) => EventKind::Delivery,
+            MessengerEvent::Read(_) => EventKind::Read,
+            MessengerEvent::Reaction(_) => EventKind::Reaction,
+            MessengerEvent::Referral(_) => EventKind::Referral,
+            MessengerEvent::Optin(_) => EventKind::Optin,
+            MessengerEvent::AccountLinking(_) => EventKind::AccountLinking,
+            MessengerEvent::HandoverProtocol(_) => EventKind::Handover,
+            MessengerEvent::PaymentChange(_) | MessengerEvent::InvoiceChange(_) | MessengerEvent::Unknown(_) => {
+                EventKind::MessagingOther
+            }
+        }
+    }
+
+    /// Registry filter name of the event this messaging item carries.
+    pub fn kind(&self) -> &'static str {
+        self.event_kind().filter_name().unwrap_or("other")
+    }
+
+    /// Subscription event type of this messaging item.
+    pub fn event_type(&self) -> &'static str {
+        self.event_kind().event_type()
+    }
+
+    /// Key identifying this event across Meta redeliveries, when it carries one.
+    pub fn dedupe_key(&self) -> Option<String> {
+        match &self.event {
+            MessengerEvent::Message(message) => message.mid.as_ref().map(|mid| format!("message:{mid}")),
+            MessengerEvent::Delivery(delivery) => {
+                Some(format!("delivery:{}:{}", delivery.mids.join(","), delivery.watermark))
+            }
+            MessengerEvent::Postback(_) => {
+                Some(format!("postback:{}:{}:{}",
+                    self.sender.id.as_deref().unwrap_or_default(),
+                    self.recipient.id.as_deref().unwrap_or_default(),
+                    self.timestamp))
+            }
+            MessengerEvent::Read(read) => {
+                Some(format!("read:{}:{}:{}",
+                    self.sender.id.as_deref().unwrap_or_default(),
+                    self.recipient.id.as_deref().unwrap_or_default(),
+                    read.watermark))
+            }
+            MessengerEvent::Reaction(reaction) => reaction.mid.as_ref().map(|mid| {
+                format!("reaction:{}:{}:{}", mid, reaction.action.as_deref().unwrap_or_default(), self.timestamp)
+            }),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -111,11 +536,106 @@ pub struct WebhookEntry {
     pub changes: Option<Vec<ChangesEvent>>,
 }
 
+impl WebhookEntry {
+    /// An entry for the same page and time without any events.
+    pub fn empty_copy(&self) -> WebhookEntry {
+        WebhookEntry {
+            id: self.id.clone(),
+            time: self.time.clone(),
+            messaging: None,
+            changes: None,
+        }
+    }
+
+    /// `true` when the entry carries no messaging events or changes.
+    pub fn is_empty(&self) -> bool {
+        self.messaging.as_ref().is_none_or(|m| m.is_empty())
+            && self.changes.as_ref().is_none_or(|c| c.is_empty())
+    }
+
+    /// Subscription event types present in the entry, without repeats.
+    pub fn event_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = Vec::new();
+        let messaging = self.messaging.iter().flatten().map(Messaging::event_type);
+        let changes = self.changes.iter().flatten().map(ChangesEvent::event_type);
+        for event_type in messaging.chain(changes) {
+            if !types.iter().any(|existing| existing == event_type) {
+                types.push(event_type.to_string());
+            }
+        }
+        types
+    }
+
+    /// Keeps only the events whose type is in `event_types`.
+    pub fn retain_event_types(&mut self, event_types: &[String]) {
+        let wanted = |event_type: &str| event_types.iter().any(|wanted| wanted == event_type);
+        if let Some(messaging) = self.messaging.as_mut() {
+            messaging.retain(|event| wanted(event.event_type()));
+        }
+        if let Some(changes) = self.changes.as_mut() {
+            changes.retain(|change| wanted(change.event_type()));
+        }
+    }
+}
+
 #[allow(dead_code)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug)]
 pub struct ChangesEvent {
     pub field: String,
-    pub value: Option<ChangeEventValue>,
+    /// `PaymentChange`, `InvoiceChange` or `Unknown` holding the raw value.
+    pub event: MessengerEvent,
+    /// Fields this model does not know, forwarded unchanged.
+    pub extra: Map<String, Value>,
+}
+
+impl<'de> Deserialize<'de> for ChangesEvent {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut fields = Map::deserialize(deserializer)?;
+        let field = take_field(&mut fields, "field")?;
+        let event = MessengerEvent::from_change_value(fields.remove("value"));
+
+        Ok(ChangesEvent {
+            field,
+            event,
+            extra: fields,
+        })
+    }
+}
+
+impl Serialize for ChangesEvent {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut fields = self.extra.clone();
+        fields.insert("field".to_string(), Value::String(self.field.clone()));
+        if let Some(value) = self.event.change_value().map_err(ser::Error::custom)? {
+            fields.insert("value".to_string(), value);
+        }
+
+        fields.serialize(serializer)
+    }
+}
+
+impl ChangesEvent {
+    /// Classifies this change.
+    pub fn event_kind(&self) -> EventKind {
+        match &self.event {
+            MessengerEvent::PaymentChange(_) => EventKind::Payment,
+            MessengerEvent::InvoiceChange(_) => EventKind::Invoice,
+            _ => EventKind::ChangesOther,
+        }
+    }
+
+    /// Subscription event type of this change.
+    pub fn event_type(&self) -> &'static str {
+        self.event_kind().event_type()
+    }
+
+    /// Key identifying this change across Meta redeliveries.
+    pub fn dedupe_key(&self) -> Option<String> {
+        match &self.event {
+            MessengerEvent::PaymentChange(value) | MessengerEvent::InvoiceChange(value) => value.dedupe_key(),
+            _ => None,
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -129,13 +649,32 @@ pub struct ChangeEventValue {
     pub timestamp: Number,
     pub event: Option<String>,
     pub payment: Option<PaymentInfo>, // P2M Bankslip field
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl ChangeEventValue {
+    /// Key identifying this change across Meta redeliveries. A payment moves
+    /// through several events, so the event name and timestamp are part of it.
+    pub fn dedupe_key(&self) -> Option<String> {
+        let event = self.event.as_deref().unwrap_or_default();
+        if let Some(payment) = &self.payment {
+            return Some(format!("payment:{}:{}:{}", payment.payment_id, event, self.timestamp));
+        }
+        self.invoice_id
+            .as_ref()
+            .map(|invoice_id| format!("invoice:{}:{}:{}", invoice_id, event, self.timestamp))
+    }
 }
 
 #[allow(dead_code)]
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PaymentAmount {
     pub amount: String,
-    pub currency: String,
+    pub currency: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
@@ -149,6 +688,8 @@ pub struct PaymentInfo {
     pub order_id: Option<String>,
     pub payment_id: String,
     pub metadata: Option<PaymentMetadata>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
@@ -169,31 +710,41 @@ pub struct PaymentMetadata {
     pub sender_bank_account_id: Option<String>,
     pub sender_bank_code: Option<String>,
     pub hpp_payment_link: Option<HppMetadata>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct HppMetadata {
-    pub psp_txn_id: String,
     pub payment_status: String,
-    pub payment_provider: String,
-    pub updated_time: String,
+    pub psp_txn_id: Option<String>,
+    pub payment_provider: Option<String>,
+    pub updated_time: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct BankSlipValidationInfo {
-    pub payment_amount: PaymentAmount,
-    pub payment_time: String,
-    pub is_seller_onboarded: bool,
-    pub matches_seller_account: bool,
-    pub is_duplicate: bool,
+    pub payment_amount: Option<PaymentAmount>,
+    pub payment_time: Option<String>,
+    pub is_seller_onboarded: Option<bool>,
+    pub matches_seller_account: Option<bool>,
+    pub is_duplicate: Option<bool>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct WrappedMessage {
     pub trace_id: String,
+    /// Webhook object the entry came from, `page` or `instagram`.
+    pub object: String,
     pub page_entry: WebhookEntry,
 }
 
@@ -204,10 +755,74 @@ pub struct MessengerWebhook {
     pub entry: Vec<WebhookEntry>,
 }
 
+/// Minimal view of a webhook body: the object and each entry's original bytes.
+/// Used before the signature is verified and by the raw forwarding mode.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookEnvelope {
+    pub object: String,
+    pub entry: Vec<Box<RawValue>>,
+}
+
+impl WebhookEnvelope {
+    /// Channel ids of every entry that parses, see [`EnvelopeEntry::ref_ids`].
+    pub fn ref_ids(&self) -> Vec<String> {
+        let channel_type = ChannelType::from_object(&self.object).unwrap_or(ChannelType::Page);
+        self.entry
+            .iter()
+            .filter_map(|entry| serde_json::from_str::<EnvelopeEntry>(entry.get()).ok())
+            .flat_map(|entry| entry.ref_ids(channel_type))
+            .collect()
+    }
+}
+
+/// [`WrappedMessage`] around an entry's original bytes.
+#[derive(Debug, Serialize)]
+pub struct RawWrappedMessage<'a> {
+    pub trace_id: &'a str,
+    pub object: &'a str,
+    pub page_entry: &'a RawValue,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EnvelopeEntry {
+    pub id: String,
+    #[serde(default)]
+    pub changes: Vec<Value>,
+}
+
+impl EnvelopeEntry {
+    /// Ids of the `merchant_channel` rows this entry routes to: the entry id,
+    /// or for WhatsApp the phone number id of each change, falling back to the
+    /// WABA id for account-level changes.
+    pub fn ref_ids(&self, channel_type: ChannelType) -> Vec<String> {
+        if channel_type != ChannelType::WhatsApp {
+            return vec![self.id.clone()];
+        }
+
+        let mut ids: Vec<String> = Vec::new();
+        for change in &self.changes {
+            let id = change
+                .pointer("/value/metadata/phone_number_id")
+                .and_then(Value::as_str)
+                .unwrap_or(&self.id);
+            if !ids.iter().any(|existing| existing == id) {
+                ids.push(id.to_string());
+            }
+        }
+        ids
+    }
+}
+
 #[allow(dead_code)]
+#[serde_with::skip_serializing_none]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct MessagePostback {
+    pub mid: Option<String>,
+    pub title: Option<String>,
     pub payload: String,
+    pub referral: Option<Referral>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 #[allow(dead_code)]
@@ -216,3 +831,166 @@ impl Postback {
         &self.payload
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_event_kind_has_one_name_entry() {
+        for (kind, _, _) in EVENT_NAMES {
+            assert_eq!(EVENT_NAMES.iter().filter(|(other, _, _)| other == kind).count(), 1);
+        }
+        assert!(EventKind::is_filter_name("message"));
+        assert!(!EventKind::is_filter_name("messages"));
+        assert!(EventKind::is_event_type("changes:payment"));
+        assert!(!EventKind::is_event_type("payment"));
+    }
+
+    #[test]
+    fn echo_is_filtered_as_message_and_subscribed_as_echo() {
+        let messaging = serde_json::from_str::<Messaging>(
+            r#"{"sender":{"id":"1"},"recipient":{"id":"2"},"timestamp":1,"message":{"mid":"m.1","is_echo":true,"text":"hi"}}"#,
+        )
+        .unwrap();
+        assert_eq!(messaging.kind(), "message");
+        assert_eq!(messaging.event_type(), "message_echoes");
+    }
+
+    /// A bank slip payment as Meta sends it for Thai P2M pages, with fields
+    /// this model does not know at every level.
+    const BANK_SLIP_CHANGE: &str = r#"{
+        "field": "invoice_access_bank_slip_events",
+        "value": {
+            "entity_id": "1234567890",
+            "event": "bank_slip_verification",
+            "page_id": "104209402365563",
+            "media_id": "1029384756",
+            "buyer_id": "6184301201660912",
+            "timestamp": 1675849212,
+            "payment": {
+                "payment_amount": "1,234.50 THB",
+                "payment_method": "bank_slip",
+                "creation_time": 1675849200,
+                "buyer_id": "6184301201660912",
+                "order_id": "ORD-1001",
+                "payment_id": "5872340120938475",
+                "metadata": {
+                    "image_url": "https://scontent.xx.fbcdn.net/v/slip.jpg",
+                    "bank_transfer_id": "202302081234567",
+                    "amount_validated": {"amount": "1234.50", "currency": "THB", "precision": 2},
+                    "transaction_time": 1675849100,
+                    "validation_status": "VALID",
+                    "validation_info": {
+                        "payment_amount": {"amount": "1234.50", "currency": "THB"},
+                        "payment_time": "2023-02-08T09:58:20+0000",
+                        "is_seller_onboarded": true,
+                        "matches_seller_account": true,
+                        "is_duplicate": false,
+                        "ocr_confidence": "high"
+                    },
+                    "receiver_name": "Shop Co., Ltd.",
+                    "sender_name": "Somchai J.",
+                    "slip_provider": "kbank"
+                },
+                "shipping_address": {"city": "Bangkok"}
+            }
+        }
+    }"#;
+
+    /// A hosted payment page update, which carries no bank slip validation.
+    const HPP_CHANGE: &str = r#"{
+        "field": "invoice_access_invoice_change",
+        "value": {
+            "page_id": "104209402365563",
+            "invoice_id": "INV-2001",
+            "event": "payment_status_update",
+            "timestamp": 1675849300,
+            "payment": {
+                "payment_amount": "PHP 499.00",
+                "payment_method": "hpp",
+                "creation_time": 1675849290,
+                "buyer_id": "6184301201660912",
+                "payment_id": "9981726354",
+                "metadata": {
+                    "hpp_payment_link": {
+                        "payment_status": "PAID",
+                        "psp_txn_id": "PSP-77812",
+                        "payment_provider": "2c2p",
+                        "updated_time": "2023-02-08T10:01:30+0000",
+                        "checkout_url_id": "cu_1"
+                    }
+                }
+            }
+        }
+    }"#;
+
+    fn round_trip(raw: &str) -> ChangesEvent {
+        let change = serde_json::from_str::<ChangesEvent>(raw).unwrap();
+        assert_eq!(serde_json::to_value(&change).unwrap(), serde_json::from_str::<Value>(raw).unwrap());
+        change
+    }
+
+    fn round_trip_messaging(raw: &str) -> Messaging {
+        let messaging = serde_json::from_str::<Messaging>(raw).unwrap();
+        assert_eq!(serde_json::to_value(&messaging).unwrap(), serde_json::from_str::<Value>(raw).unwrap());
+        messaging
+    }
+
+    #[test]
+    fn checkbox_optin_round_trips_without_sender_id() {
+        let messaging = round_trip_messaging(
+            r#"{"sender":{"user_ref":"ref-1"},"recipient":{"id":"2","extra_field":true},"timestamp":1,"optin":{"ref":"shop","user_ref":"ref-1"}}"#,
+        );
+        assert_eq!(messaging.sender.id, None);
+        assert_eq!(messaging.sender.extra["user_ref"], "ref-1");
+        assert_eq!(messaging.recipient.id.as_deref(), Some("2"));
+        assert_eq!(messaging.event_kind(), EventKind::Optin);
+    }
+
+    #[test]
+    fn delivery_without_mids_round_trips() {
+        round_trip_messaging(r#"{"sender":{"id":"1"},"recipient":{"id":"2"},"timestamp":1,"delivery":{"watermark":1}}"#);
+        round_trip_messaging(r#"{"sender":{"id":"1"},"recipient":{"id":"2"},"timestamp":1,"delivery":{"mids":["m.1"],"watermark":1}}"#);
+    }
+
+    #[test]
+    fn bank_slip_change_round_trips_unknown_fields() {
+        let change = round_trip(BANK_SLIP_CHANGE);
+        let MessengerEvent::PaymentChange(value) = &change.event else {
+            panic!("expected a payment change, got {:?}", change.event);
+        };
+        let metadata = value.payment.as_ref().and_then(|payment| payment.metadata.as_ref()).unwrap();
+        let info = metadata.validation_info.as_ref().unwrap();
+        assert_eq!(info.is_duplicate, Some(false));
+        assert!(info.extra.contains_key("ocr_confidence"));
+        assert!(metadata.amount_validated.as_ref().unwrap().extra.contains_key("precision"));
+    }
+
+    #[test]
+    fn hpp_change_round_trips_unknown_fields() {
+        let change = round_trip(HPP_CHANGE);
+        let MessengerEvent::PaymentChange(value) = &change.event else {
+            panic!("expected a payment change, got {:?}", change.event);
+        };
+        let hpp = value
+            .payment
+            .as_ref()
+            .and_then(|payment| payment.metadata.as_ref())
+            .and_then(|metadata| metadata.hpp_payment_link.as_ref())
+            .unwrap();
+        assert_eq!(hpp.payment_status, "PAID");
+        assert!(hpp.extra.contains_key("checkout_url_id"));
+    }
+
+    #[test]
+    fn payment_metadata_without_optional_fields_stays_a_payment() {
+        let change = serde_json::from_str::<ChangesEvent>(
+            r#"{"field":"invoice_access_bank_slip_events","value":{"page_id":"1","timestamp":1,"payment":{
+                "payment_amount":"100","payment_method":"bank_slip","creation_time":1,"buyer_id":"2","payment_id":"3",
+                "metadata":{"hpp_payment_link":{"payment_status":"PENDING"},"validation_info":{"is_duplicate":true}}}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(change.event, MessengerEvent::PaymentChange(_)));
+    }
+}
@@ -1,6 +1,16 @@
+pub mod api_key;
 pub mod application;
+pub mod application_registry;
 pub mod health_check;
 pub mod merchant_channel;
 pub mod search_application;
 pub mod messenger_webhook;
-pub mod merchant_config;
\ No newline at end of file
+pub mod merchant_config;
+pub mod outbox;
+pub mod subscription;
+pub mod whatsapp_webhook;pub mod send;
+pub mod payment_event;
+pub mod payment;
+pub mod payment_alert;
+pub mod archive;
+pub mod ingest;
@@ -0,0 +1,32 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NewOutboxMessage {
+    pub app_id: i32,
+    pub topic: String,
+    pub sink_type: String,
+    pub payload: String,
+    pub trace_id: Option<String>,
+}
+
+impl NewOutboxMessage {
+    pub fn new(app_id: i32, topic: String, sink_type: String, payload: String, trace_id: Option<String>) -> Self {
+        Self {
+            app_id,
+            topic,
+            sink_type,
+            payload,
+            trace_id,
+        }
+    }
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct OutboxMessage {
+    pub id: i64,
+    pub app_id: i32,
+    pub topic: String,
+    pub sink_type: String,
+    pub payload: String,
+    pub attempts: i32,
+}
@@ -0,0 +1,159 @@
+use chrono::{DateTime, TimeZone, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::types::Json;
+use validator::Validate;
+
+use crate::models::{
+    messenger_webhook::{ChangesEvent, MessengerEvent},
+    payment_event::PaymentEvent,
+};
+
+/// A payment or invoice change to write to `payment` and
+/// `payment_status_history`.
+#[derive(Clone, Debug)]
+pub struct PaymentRecord {
+    pub event: PaymentEvent,
+    /// `PaymentMetadata` as Meta sent it.
+    pub metadata: Option<Value>,
+    pub event_at: DateTime<Utc>,
+    pub trace_id: String,
+}
+
+impl PaymentRecord {
+    pub fn from_change(change: &ChangesEvent, trace_id: &str) -> Option<Self> {
+        let event = PaymentEvent::from_change(change)?;
+        let metadata = match &change.event {
+            MessengerEvent::PaymentChange(value) | MessengerEvent::InvoiceChange(value) => value
+                .payment
+                .as_ref()
+                .and_then(|payment| payment.metadata.as_ref())
+                .and_then(|metadata| serde_json::to_value(metadata).ok()),
+            _ => None,
+        };
+
+        Some(PaymentRecord {
+            event_at: event_time(&event),
+            event,
+            metadata,
+            trace_id: trace_id.to_string(),
+        })
+    }
+}
+
+/// Change timestamps arrive in seconds or milliseconds depending on the event.
+fn event_time(event: &PaymentEvent) -> DateTime<Utc> {
+    let timestamp = event.timestamp.as_i64().unwrap_or_default();
+    let time = if timestamp > 100_000_000_000 {
+        Utc.timestamp_millis_opt(timestamp)
+    } else {
+        Utc.timestamp_opt(timestamp, 0)
+    };
+    time.single().unwrap_or_else(Utc::now)
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct Payment {
+    pub payment_id: String,
+    pub page_id: String,
+    pub buyer_id: Option<String>,
+    pub order_id: Option<String>,
+    pub invoice_id: Option<String>,
+    pub payment_method: Option<String>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<String>,
+    pub status: String,
+    pub is_duplicate: bool,
+    pub metadata: Option<Json<Value>>,
+    pub last_event_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
+pub struct PaymentStatusChange {
+    pub event: Option<String>,
+    pub status: String,
+    pub event_at: DateTime<Utc>,
+    pub trace_id: Option<String>,
+}
+
+#[serde_with::skip_serializing_none]
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PaymentResponse {
+    pub payment_id: String,
+    pub page_id: String,
+    pub buyer_id: Option<String>,
+    pub order_id: Option<String>,
+    pub invoice_id: Option<String>,
+    pub payment_method: Option<String>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<String>,
+    pub status: String,
+    pub is_duplicate: bool,
+    pub metadata: Option<Value>,
+    pub last_event_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    /// Only filled in by `GET /payments/{payment_id}`.
+    pub history: Option<Vec<PaymentStatusChange>>,
+}
+
+impl From<Payment> for PaymentResponse {
+    fn from(p: Payment) -> Self {
+        Self {
+            payment_id: p.payment_id,
+            page_id: p.page_id,
+            buyer_id: p.buyer_id,
+            order_id: p.order_id,
+            invoice_id: p.invoice_id,
+            payment_method: p.payment_method,
+            amount: p.amount,
+            currency: p.currency,
+            status: p.status,
+            is_duplicate: p.is_duplicate,
+            metadata: p.metadata.map(|metadata| metadata.0),
+            last_event_at: p.last_event_at,
+            created_at: p.created_at,
+            updated_at: p.updated_at,
+            history: None,
+        }
+    }
+}
+
+fn default_limit() -> u32 {
+    50
+}
+
+/// Largest offset Postgres accepts, which takes a `bigint`.
+const MAX_OFFSET: u64 = i64::MAX as u64;
+
+/// Filters of `GET /payments`. `from` and `to` bound when the payment was
+/// first seen, `to` exclusive.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct SearchPayments {
+    pub page_id: Option<String>,
+    pub buyer_id: Option<String>,
+    pub order_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[validate(range(max = MAX_OFFSET))]
+    pub offset: u64,
+    #[serde(default = "default_limit")]
+    #[validate(range(min = 1, max = 500))]
+    pub limit: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn search_offset_must_fit_a_bigint() {
+        let search = |offset: u64| serde_json::from_value::<SearchPayments>(serde_json::json!({ "offset": offset })).unwrap();
+        assert!(search(MAX_OFFSET).validate().is_ok());
+        assert!(search(MAX_OFFSET + 1).validate().is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use std::{fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AppError;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertRule {
+    /// Meta saw the same bank slip before.
+    DuplicateSlip,
+    /// The amount read from the slip differs from the amount paid.
+    AmountMismatch,
+    /// The slip was paid to another account than the seller's.
+    SellerAccountMismatch,
+    /// The seller has not completed bank slip onboarding.
+    SellerNotOnboarded,
+}
+
+impl AlertRule {
+    pub const ALL: [AlertRule; 4] = [
+        AlertRule::DuplicateSlip,
+        AlertRule::AmountMismatch,
+        AlertRule::SellerAccountMismatch,
+        AlertRule::SellerNotOnboarded,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertRule::DuplicateSlip => "duplicate_slip",
+            AlertRule::AmountMismatch => "amount_mismatch",
+            AlertRule::SellerAccountMismatch => "seller_account_mismatch",
+            AlertRule::SellerNotOnboarded => "seller_not_onboarded",
+        }
+    }
+}
+
+impl fmt::Display for AlertRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AlertRule {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AlertRule::ALL
+            .into_iter()
+            .find(|rule| rule.as_str() == s)
+            .ok_or_else(|| AppError::InternalServerError(format!("unknown alert rule: {s}")))
+    }
+}
+
+/// A rule hit on one payment, as stored in `payment_alert` and published to
+/// the alerts topic.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaymentAlert {
+    pub rule: AlertRule,
+    pub payment_id: String,
+    pub page_id: String,
+    pub buyer_id: Option<String>,
+    pub order_id: Option<String>,
+    pub detail: String,
+    pub event_at: DateTime<Utc>,
+    pub trace_id: String,
+}
+
+/// A stored alert still waiting to be published, with the buyer and order of
+/// its payment.
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct PaymentAlertRow {
+    pub id: i64,
+    pub rule: String,
+    pub payment_id: String,
+    pub page_id: String,
+    pub buyer_id: Option<String>,
+    pub order_id: Option<String>,
+    pub detail: String,
+    pub event_at: DateTime<Utc>,
+    pub trace_id: Option<String>,
+}
+
+impl TryFrom<PaymentAlertRow> for (i64, PaymentAlert) {
+    type Error = AppError;
+
+    fn try_from(row: PaymentAlertRow) -> Result<Self, Self::Error> {
+        let alert = PaymentAlert {
+            rule: row.rule.parse()?,
+            payment_id: row.payment_id,
+            page_id: row.page_id,
+            buyer_id: row.buyer_id,
+            order_id: row.order_id,
+            detail: row.detail,
+            event_at: row.event_at,
+            trace_id: row.trace_id.unwrap_or_default(),
+        };
+        Ok((row.id, alert))
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PublishedPaymentAlert<'a> {
+    pub alert_id: i64,
+    #[serde(flatten)]
+    pub alert: &'a PaymentAlert,
+}
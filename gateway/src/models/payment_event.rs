@@ -0,0 +1,297 @@
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use serde_json::Number;
+
+use crate::models::messenger_webhook::{ChangeEventValue, ChangesEvent, MessengerEvent, PaymentAmount};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentEventKind {
+    /// A bank slip or hosted payment page payment.
+    Payment,
+    /// An invoice update without payment details.
+    Invoice,
+}
+
+/// Outcome of Meta's bank slip check.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaymentValidation {
+    pub status: Option<String>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<String>,
+    /// Whether the validated amount equals the claimed one.
+    pub amount_matches: Option<bool>,
+    pub seller_onboarded: Option<bool>,
+    pub matches_seller_account: Option<bool>,
+}
+
+/// A P2M payment or invoice change in a stable shape: the amount is a decimal
+/// with its currency and the status is lower case, whichever of Meta's fields
+/// it came from.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaymentEvent {
+    pub kind: PaymentEventKind,
+    pub page_id: String,
+    pub event: Option<String>,
+    pub status: String,
+    pub payment_id: Option<String>,
+    pub invoice_id: Option<String>,
+    pub order_id: Option<String>,
+    pub buyer_id: Option<String>,
+    pub payment_method: Option<String>,
+    pub amount: Option<Decimal>,
+    pub currency: Option<String>,
+    pub validation: Option<PaymentValidation>,
+    /// Meta flagged the bank slip as already submitted.
+    pub is_duplicate: bool,
+    pub timestamp: Number,
+}
+
+impl PaymentEvent {
+    /// `None` unless `change` is a payment or invoice change.
+    pub fn from_change(change: &ChangesEvent) -> Option<Self> {
+        match &change.event {
+            MessengerEvent::PaymentChange(value) | MessengerEvent::InvoiceChange(value) => Some(Self::from_value(value)),
+            _ => None,
+        }
+    }
+
+    fn from_value(value: &ChangeEventValue) -> Self {
+        let payment = value.payment.as_ref();
+        let metadata = payment.and_then(|payment| payment.metadata.as_ref());
+        let validation_info = metadata.and_then(|metadata| metadata.validation_info.as_ref());
+
+        let (amount, currency) = payment
+            .map(|payment| parse_amount(&payment.payment_amount))
+            .unwrap_or_default();
+        let validated = metadata
+            .and_then(|metadata| metadata.amount_validated.as_ref())
+            .or(validation_info.and_then(|info| info.payment_amount.as_ref()))
+            .map(parse_payment_amount);
+        let currency = currency.or_else(|| validated.as_ref().and_then(|(_, currency)| currency.clone()));
+
+        let validation = metadata
+            .filter(|metadata| metadata.validation_status.is_some() || validated.is_some() || validation_info.is_some())
+            .map(|metadata| {
+                let (validated_amount, validated_currency) = validated.clone().unwrap_or_default();
+                PaymentValidation {
+                    status: metadata.validation_status.as_deref().map(str::to_lowercase),
+                    amount_matches: amount.zip(validated_amount).map(|(claimed, validated)| claimed == validated),
+                    amount: validated_amount,
+                    currency: validated_currency,
+                    seller_onboarded: validation_info.and_then(|info| info.is_seller_onboarded),
+                    matches_seller_account: validation_info.and_then(|info| info.matches_seller_account),
+                }
+            });
+
+        let status = metadata
+            .and_then(|metadata| metadata.hpp_payment_link.as_ref())
+            .map(|hpp| hpp.payment_status.as_str())
+            .or(metadata.and_then(|metadata| metadata.validation_status.as_deref()))
+            .or(value.event.as_deref())
+            .unwrap_or("unknown")
+            .to_lowercase();
+
+        PaymentEvent {
+            kind: if payment.is_some() { PaymentEventKind::Payment } else { PaymentEventKind::Invoice },
+            page_id: value.page_id.clone(),
+            event: value.event.clone(),
+            status,
+            payment_id: payment.map(|payment| payment.payment_id.clone()),
+            invoice_id: value.invoice_id.clone(),
+            order_id: payment.and_then(|payment| payment.order_id.clone()),
+            buyer_id: payment.map(|payment| payment.buyer_id.clone()).or(value.buyer_id.clone()),
+            payment_method: payment.map(|payment| payment.payment_method.clone()),
+            amount,
+            currency,
+            validation,
+            is_duplicate: validation_info.and_then(|info| info.is_duplicate).unwrap_or(false),
+            timestamp: value.timestamp.clone(),
+        }
+    }
+}
+
+fn parse_payment_amount(amount: &PaymentAmount) -> (Option<Decimal>, Option<String>) {
+    let (value, embedded) = parse_amount(&amount.amount);
+    let currency = amount
+        .currency
+        .as_deref()
+        .map(|currency| currency.trim().to_uppercase())
+        .filter(|currency| !currency.is_empty());
+    (value, currency.or(embedded))
+}
+
+/// Splits amounts such as `"1,234.50"`, `"PHP 1234.5"`, `"1 234.50"` or
+/// `"1.234,50 THB"` into a decimal and an optional ISO currency code. The
+/// amount is `None` whenever it could be read more than one way.
+fn parse_amount(raw: &str) -> (Option<Decimal>, Option<String>) {
+    let mut currency = None;
+    let mut parts = Vec::new();
+    for part in raw.split_whitespace() {
+        if part.len() == 3 && part.chars().all(|c| c.is_ascii_alphabetic()) {
+            if currency.replace(part.to_uppercase()).is_some() {
+                return (None, None);
+            }
+        } else {
+            parts.push(part);
+        }
+    }
+
+    let amount = match parts.as_slice() {
+        [] => None,
+        [single] => parse_decimal(single),
+        [first, groups @ ..] if is_space_grouped(first, groups) => parse_decimal(&parts.concat()),
+        _ => None,
+    };
+    (amount, currency)
+}
+
+/// `true` for thousands separated by spaces, e.g. `["1", "234.50"]`: every
+/// group after the first is three digits, the last one optionally followed by
+/// a decimal part.
+fn is_space_grouped(first: &str, groups: &[&str]) -> bool {
+    let three_digits = |group: &str| group.len() >= 3 && group.as_bytes()[..3].iter().all(u8::is_ascii_digit);
+    let Some((last, middle)) = groups.split_last() else {
+        return false;
+    };
+
+    first.ends_with(|c: char| c.is_ascii_digit())
+        && middle.iter().all(|group| group.len() == 3 && three_digits(group))
+        && three_digits(last)
+        && matches!(last.as_bytes().get(3), None | Some(b'.') | Some(b','))
+}
+
+/// Parses a number written with `.` or `,` as the decimal separator and the
+/// other one, if any, grouping thousands. A single separator followed by
+/// exactly three digits, as in `1,234` or `1.234`, is ambiguous and gives
+/// `None`, as does anything that is not a well formed number.
+fn parse_decimal(raw: &str) -> Option<Decimal> {
+    // currency symbols such as `฿` or `$` around the number
+    let is_symbol = |c: char| !c.is_alphanumeric() && !matches!(c, '-' | '.' | ',');
+    let raw = raw.trim_matches(is_symbol);
+    let (negative, digits) = match raw.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, raw),
+    };
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',') {
+        return None;
+    }
+
+    let dots = digits.matches('.').count();
+    let commas = digits.matches(',').count();
+    let (integer, fraction) = match (dots, commas) {
+        (0, 0) => (digits.to_string(), None),
+        // the separator that comes last is the decimal one, and appears once
+        (1, _) | (_, 1) if dots > 0 && commas > 0 => {
+            let (decimal, group) = if digits.rfind(',') > digits.rfind('.') { (',', '.') } else { ('.', ',') };
+            let (integer, fraction) = digits.rsplit_once(decimal)?;
+            if fraction.contains(group) || digits.matches(decimal).count() != 1 {
+                return None;
+            }
+            (ungroup(integer, group)?, Some(fraction))
+        }
+        (_, 0) | (0, _) => {
+            let separator = if dots > 0 { '.' } else { ',' };
+            let (integer, fraction) = digits.split_once(separator)?;
+            if dots + commas > 1 {
+                // only thousands can repeat
+                (ungroup(digits, separator)?, None)
+            } else if fraction.len() != 3 || integer.len() > 3 || integer.trim_start_matches('0').is_empty() {
+                (integer.to_string(), Some(fraction))
+            } else {
+                return None;
+            }
+        }
+        _ => return None,
+    };
+
+    if integer.is_empty() || fraction.is_some_and(|fraction| fraction.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    let sign = if negative { "-" } else { "" };
+    let normalized = match fraction {
+        Some(fraction) => format!("{sign}{integer}.{fraction}"),
+        None => format!("{sign}{integer}"),
+    };
+    Decimal::from_str(&normalized).ok()
+}
+
+/// Removes `separator` from `1,234,567`-style groups, `None` unless the first
+/// group has one to three digits and every other group exactly three.
+fn ungroup(raw: &str, separator: char) -> Option<String> {
+    let mut groups = raw.split(separator);
+    let first = groups.next()?;
+    let valid = (1..=3).contains(&first.len())
+        && first.chars().all(|c| c.is_ascii_digit())
+        && groups.all(|group| group.len() == 3 && group.chars().all(|c| c.is_ascii_digit()));
+    valid.then(|| raw.replace(separator, ""))
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WrappedPaymentEvent {
+    pub trace_id: String,
+    pub payment_event: PaymentEvent,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(raw: &str) -> Option<Decimal> {
+        Some(Decimal::from_str(raw).unwrap())
+    }
+
+    #[test]
+    fn parse_amount_reads_unambiguous_amounts() {
+        let cases: &[(&str, Option<Decimal>, Option<&str>)] = &[
+            ("1,234.50", decimal("1234.50"), None),
+            ("1.234,50", decimal("1234.50"), None),
+            ("1,50", decimal("1.50"), None),
+            ("฿1,234.50", decimal("1234.50"), None),
+            ("1 234.50", decimal("1234.50"), None),
+            ("1 234 567,5 THB", decimal("1234567.5"), Some("THB")),
+            ("PHP 1234.5", decimal("1234.5"), Some("PHP")),
+            ("1.234,50 thb", decimal("1234.50"), Some("THB")),
+            ("1,234,567", decimal("1234567"), None),
+            ("1,234,567.89", decimal("1234567.89"), None),
+            ("0.125", decimal("0.125"), None),
+            ("1234.567", decimal("1234.567"), None),
+            ("100", decimal("100"), None),
+            ("-1,234.50", decimal("-1234.50"), None),
+            ("-5", decimal("-5"), None),
+            ("-฿12.50", None, None),
+        ];
+        for (raw, amount, currency) in cases {
+            assert_eq!(parse_amount(raw), (*amount, currency.map(str::to_string)), "{raw}");
+        }
+    }
+
+    #[test]
+    fn parse_amount_refuses_ambiguous_or_garbage_amounts() {
+        let cases = [
+            "1,234",
+            "1.234",
+            "12,345",
+            "",
+            "abc",
+            "12abc",
+            "1.2.3",
+            "1,2,3",
+            "1,234.5,6",
+            "1,23,456.00",
+            "--5",
+            "1.",
+            ",5",
+            "1 23",
+            "12 345 67",
+            "100 THB USD",
+        ];
+        for raw in cases {
+            assert_eq!(parse_amount(raw).0, None, "{raw}");
+        }
+    }
+}
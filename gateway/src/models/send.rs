@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+/// A Send API call on behalf of `page_id`. Everything except `page_id` and
+/// `trace_id` is passed to Graph as is, see
+/// <https://developers.facebook.com/docs/messenger-platform/reference/send-api>.
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize, Validate)]
+pub struct SendMessageRequest {
+    #[serde(skip_serializing)]
+    #[validate(length(min = 1, max = 64))]
+    pub page_id: String,
+    pub recipient: Value,
+    pub messaging_type: Option<String>,
+    pub message: Option<Value>,
+    pub sender_action: Option<String>,
+    pub tag: Option<String>,
+    /// Correlates a reply-topic message with the webhook it answers.
+    #[serde(skip_serializing)]
+    pub trace_id: Option<String>,
+    /// Application sending a reply-channel message, which must be registered
+    /// to `page_id`. `POST /send` uses the caller's credential instead.
+    #[serde(skip_serializing)]
+    pub app_id: Option<String>,
+}
+
+impl SendMessageRequest {
+    /// Graph needs either a message or a sender action.
+    pub fn has_content(&self) -> bool {
+        self.message.is_some() || self.sender_action.is_some()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SendMessageResponse {
+    pub recipient_id: Option<String>,
+    pub message_id: Option<String>,
+}
+
+/// Body of a failed Graph call.
+#[derive(Debug, Deserialize)]
+pub struct GraphErrorResponse {
+    pub error: GraphError,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GraphError {
+    pub message: String,
+    pub code: Option<i64>,
+    pub error_subcode: Option<i64>,
+    pub fbtrace_id: Option<String>,
+}
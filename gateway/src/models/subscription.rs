@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+use crate::models::messenger_webhook::EventKind;
+
+fn validate_event_type(event_type: &str) -> Result<(), ValidationError> {
+  if EventKind::is_event_type(event_type) {
+    Ok(())
+  } else {
+    Err(ValidationError::new("event_type"))
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Validate, sqlx::FromRow)]
+pub struct Subscription {
+  #[validate(custom(function = "validate_event_type"))]
+  pub event_type: String,
+  /// Overrides the application topic for this event type.
+  #[validate(length(min = 1, max = 255))]
+  pub topic: Option<String>,
+}
+
+/// Replaces every subscription of an application. An empty list subscribes it
+/// to everything.
+#[derive(Debug, Serialize, Deserialize, Validate)]
+pub struct UpdateSubscriptionsRequest {
+  #[validate(length(max = 100), nested)]
+  pub subscriptions: Vec<Subscription>,
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// One `whatsapp_business_account` entry. `id` is the WABA id; routing uses the
+/// phone number id of each change instead, or the WABA id for account-level
+/// changes such as `account_update` that carry no phone number.
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppEntry {
+    pub id: String,
+    #[serde(default)]
+    pub changes: Vec<WhatsAppChange>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl WhatsAppEntry {
+    /// Channel id `change` routes to: its phone number id, or the WABA id.
+    fn routing_id<'a>(&'a self, change: &'a WhatsAppChange) -> &'a str {
+        change
+            .value
+            .metadata
+            .as_ref()
+            .map(|metadata| metadata.phone_number_id.as_str())
+            .unwrap_or(&self.id)
+    }
+
+    /// Channel ids the changes of this entry route to, without repeats.
+    pub fn routing_ids(&self) -> Vec<String> {
+        let mut ids: Vec<String> = Vec::new();
+        for change in &self.changes {
+            let id = self.routing_id(change);
+            if !ids.iter().any(|existing| existing == id) {
+                ids.push(id.to_string());
+            }
+        }
+        ids
+    }
+
+    /// An entry holding only the changes routed to `routing_id`.
+    pub fn for_routing_id(&self, routing_id: &str) -> WhatsAppEntry {
+        WhatsAppEntry {
+            id: self.id.clone(),
+            changes: self
+                .changes
+                .iter()
+                .filter(|change| self.routing_id(change) == routing_id)
+                .cloned()
+                .collect(),
+            extra: self.extra.clone(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.iter().all(WhatsAppChange::is_empty)
+    }
+
+    /// `whatsapp:<field>` of each change, without repeats.
+    pub fn event_types(&self) -> Vec<String> {
+        let mut types: Vec<String> = Vec::new();
+        for change in &self.changes {
+            let event_type = format!("whatsapp:{}", change.field);
+            if !types.contains(&event_type) {
+                types.push(event_type);
+            }
+        }
+        types
+    }
+
+    /// Keeps only the changes whose `whatsapp:<field>` type is in `event_types`.
+    pub fn retain_event_types(&mut self, event_types: &[String]) {
+        self.changes
+            .retain(|change| event_types.iter().any(|wanted| *wanted == format!("whatsapp:{}", change.field)));
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppChange {
+    pub field: String,
+    pub value: WhatsAppValue,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl WhatsAppChange {
+    /// `true` when a `messages` change has nothing left after dedupe.
+    pub fn is_empty(&self) -> bool {
+        let value = &self.value;
+        value.messages.as_ref().is_none_or(|m| m.is_empty())
+            && value.statuses.as_ref().is_none_or(|s| s.is_empty())
+            && value.errors.as_ref().is_none_or(|e| e.is_empty())
+            && value.extra.is_empty()
+    }
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppValue {
+    pub messaging_product: Option<String>,
+    /// Missing on account-level changes, which belong to the WABA itself.
+    pub metadata: Option<WhatsAppMetadata>,
+    pub contacts: Option<Vec<WhatsAppContact>>,
+    pub messages: Option<Vec<WhatsAppMessage>>,
+    pub statuses: Option<Vec<WhatsAppStatus>>,
+    pub errors: Option<Vec<Value>>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[allow(dead_code)]
+#[serde_with::skip_serializing_none]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppMetadata {
+    pub display_phone_number: Option<String>,
+    pub phone_number_id: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppContact {
+    pub wa_id: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// An inbound message. The body sits under the key named by `type`, e.g.
+/// `text` or `image`, and is kept in `extra`.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppMessage {
+    pub id: String,
+    pub from: String,
+    pub timestamp: String,
+    #[serde(rename = "type")]
+    pub message_type: String,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl WhatsAppMessage {
+    pub fn dedupe_key(&self) -> String {
+        format!("wa:message:{}", self.id)
+    }
+}
+
+/// Delivery status of a message the business sent: `sent`, `delivered`,
+/// `read` or `failed`.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WhatsAppStatus {
+    pub id: String,
+    pub status: String,
+    pub timestamp: String,
+    pub recipient_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl WhatsAppStatus {
+    pub fn dedupe_key(&self) -> String {
+        format!("wa:status:{}:{}", self.id, self.status)
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct WrappedWhatsAppMessage {
+    pub trace_id: String,
+    pub object: String,
+    pub entry: WhatsAppEntry,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIXED_ENTRY: &str = r#"{
+        "id": "102290129340398",
+        "changes": [
+            {
+                "field": "messages",
+                "value": {
+                    "messaging_product": "whatsapp",
+                    "metadata": {"display_phone_number": "15550783881", "phone_number_id": "106540352242922"},
+                    "messages": [{"from": "16505551234", "id": "wamid.HBgLMTY1MDM4Nzk0MzkVAgASGBQzQTRBNjU5OUFFRTAzODEwMTQ0RgA=", "timestamp": "1749416383", "type": "text", "text": {"body": "Hi"}}]
+                }
+            },
+            {
+                "field": "account_update",
+                "value": {"phone_number": "15550783881", "event": "VERIFIED_ACCOUNT"}
+            },
+            {
+                "field": "message_template_status_update",
+                "value": {"event": "APPROVED", "message_template_id": 594425479261596, "message_template_name": "order_confirmation", "message_template_language": "en_US", "reason": "NONE"}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn account_level_changes_route_by_waba_id() {
+        let entry = serde_json::from_str::<WhatsAppEntry>(MIXED_ENTRY).unwrap();
+        assert_eq!(entry.routing_ids(), vec!["106540352242922".to_string(), "102290129340398".to_string()]);
+
+        let account = entry.for_routing_id("102290129340398");
+        assert_eq!(account.event_types(), vec!["whatsapp:account_update", "whatsapp:message_template_status_update"]);
+        assert!(!account.is_empty());
+
+        let phone = entry.for_routing_id("106540352242922");
+        assert_eq!(phone.event_types(), vec!["whatsapp:messages"]);
+    }
+
+    #[test]
+    fn account_level_change_round_trips_without_metadata() {
+        let entry = serde_json::from_str::<WhatsAppEntry>(MIXED_ENTRY).unwrap();
+        let serialized = serde_json::to_value(&entry).unwrap();
+        assert_eq!(serialized, serde_json::from_str::<Value>(MIXED_ENTRY).unwrap());
+    }
+}
@@ -0,0 +1,221 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use emit::{__emit_get_event_data, emit, error, info};
+use tokio::{sync::Notify, task::JoinHandle};
+
+use crate::{
+    database::Database,
+    errors::AppError,
+    models::outbox::{NewOutboxMessage, OutboxMessage},
+    sinks::{MessageSinks, SinkKind},
+    utils::config::env_or,
+};
+
+/// How often delivered messages past the retention are purged.
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Rows deleted per purge statement.
+const PURGE_CHUNK: i64 = 10_000;
+
+#[derive(Clone, Debug)]
+pub struct OutboxConfig {
+    pub batch_size: i64,
+    pub poll_interval: Duration,
+    pub lease: Duration,
+    pub max_attempts: i32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// How long delivered messages are kept before the purge deletes them.
+    pub retention: Duration,
+}
+
+impl OutboxConfig {
+    pub fn from_env() -> Self {
+        OutboxConfig {
+            batch_size: env_or("OUTBOX_BATCH_SIZE", 100),
+            poll_interval: Duration::from_millis(env_or("OUTBOX_POLL_INTERVAL_MS", 1_000)),
+            lease: Duration::from_secs(env_or("OUTBOX_LEASE_SECS", 60)),
+            max_attempts: env_or("OUTBOX_MAX_ATTEMPTS", 10),
+            base_backoff: Duration::from_millis(env_or("OUTBOX_BASE_BACKOFF_MS", 1_000)),
+            max_backoff: Duration::from_secs(env_or("OUTBOX_MAX_BACKOFF_SECS", 600)),
+            retention: Duration::from_secs(env_or("OUTBOX_RETENTION_HOURS", 168) * 3600),
+        }
+    }
+}
+
+/// Webhook entries are written here inside the request and delivered to their
+/// app sink by a background worker, so a sink outage only delays delivery.
+#[derive(Clone)]
+pub struct Outbox {
+    database: Database,
+    sinks: MessageSinks,
+    notify: Arc<Notify>,
+    config: OutboxConfig,
+}
+
+impl Outbox {
+    pub fn new(database: Database, sinks: MessageSinks, config: OutboxConfig) -> Self {
+        Outbox {
+            database,
+            sinks,
+            notify: Arc::new(Notify::new()),
+            config,
+        }
+    }
+
+    pub async fn enqueue(&self, messages: Vec<NewOutboxMessage>, parked: Vec<NewOutboxMessage>) -> Result<(), AppError> {
+        if messages.is_empty() && parked.is_empty() {
+            return Ok(());
+        }
+
+        self.database.enqueue_outbox(&messages, &parked).await?;
+        if !messages.is_empty() {
+            self.notify.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Starts delivery of messages written to the outbox by another
+    /// transaction, see [`Database::record_webhook`].
+    pub fn wake(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Queues the events parked for `app_id` while it was disabled.
+    pub async fn replay_parked(&self, app_id: &str) -> Result<u64, AppError> {
+        let replayed = self.database.replay_parked_events(app_id).await?;
+        if replayed > 0 {
+            self.notify.notify_one();
+        }
+
+        Ok(replayed)
+    }
+
+    pub fn spawn_worker(&self) -> JoinHandle<()> {
+        let outbox = self.clone();
+        tokio::spawn(async move { outbox.run().await })
+    }
+
+    async fn run(&self) {
+        info!("Outbox worker started, batch size: {}", batch_size: self.config.batch_size);
+
+        let mut next_purge = Instant::now();
+        loop {
+            if Instant::now() >= next_purge {
+                self.purge().await;
+                next_purge = Instant::now() + PURGE_INTERVAL;
+            }
+
+            match self.deliver_batch().await {
+                // A full batch means there is likely more work waiting.
+                Ok(delivered) if delivered as i64 >= self.config.batch_size => continue,
+                Ok(_) => {}
+                Err(err) => {
+                    error!("Outbox worker failed to claim messages, error: {}", error: err.to_string());
+                }
+            }
+
+            tokio::select! {
+                _ = self.notify.notified() => {}
+                _ = tokio::time::sleep(self.config.poll_interval) => {}
+            }
+        }
+    }
+
+    /// Deletes delivered messages older than the retention, in chunks so a
+    /// large backlog does not hold long locks.
+    async fn purge(&self) {
+        let mut purged = 0;
+        loop {
+            match self
+                .database
+                .purge_delivered_outbox(self.config.retention.as_secs_f64(), PURGE_CHUNK)
+                .await
+            {
+                Ok(deleted) => {
+                    purged += deleted;
+                    if deleted < PURGE_CHUNK as u64 {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    error!("Outbox purge failed, error: {}", error: err.to_string());
+                    break;
+                }
+            }
+        }
+        if purged > 0 {
+            info!("Purged {} delivered outbox messages", purged: purged);
+        }
+    }
+
+    async fn deliver_batch(&self) -> Result<usize, AppError> {
+        let messages = self
+            .database
+            .claim_outbox(self.config.batch_size, self.config.lease.as_secs_f64())
+            .await?;
+        let claimed = messages.len();
+
+        // A message whose outcome could not be recorded stays leased and is
+        // retried once the lease runs out; the rest of the batch goes on.
+        for message in messages {
+            let id = message.id;
+            if let Err(err) = self.deliver(message).await {
+                error!("Outbox worker failed to record the outcome of message {}, error: {}", outbox_id: id, error: err.to_string());
+            }
+        }
+
+        Ok(claimed)
+    }
+
+    async fn deliver(&self, message: OutboxMessage) -> Result<(), AppError> {
+        let result = match message.sink_type.parse::<SinkKind>() {
+            Ok(sink) => {
+                self.sinks
+                    .publish(sink, &message.topic, &message.payload)
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => self.database.mark_outbox_delivered(message.id).await,
+            Err(err) => {
+                let attempts = message.attempts + 1;
+                let retry_in = if attempts >= self.config.max_attempts {
+                    error!("Outbox message {} moved to dead letter after {} attempts, app_id: {}, topic: {}, error: {}",
+                        outbox_id: message.id,
+                        attempts: attempts,
+                        app_id: message.app_id,
+                        topic: message.topic,
+                        error: err.to_string());
+                    None
+                } else {
+                    let backoff = self.backoff(attempts);
+                    info!("Outbox message {} failed, attempt {}, retrying in ms {}, error: {}",
+                        outbox_id: message.id,
+                        attempts: attempts,
+                        retry_in_ms: backoff.as_millis() as u64,
+                        error: err.to_string());
+                    Some(backoff.as_secs_f64())
+                };
+
+                self.database
+                    .mark_outbox_failed(message.id, &err.to_string(), retry_in)
+                    .await
+            }
+        }
+    }
+
+    /// Exponential backoff: `base * 2^(attempts - 1)`, capped at `max_backoff`.
+    fn backoff(&self, attempts: i32) -> Duration {
+        let exponent = attempts.saturating_sub(1).clamp(0, 31) as u32;
+        self.config
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.config.max_backoff)
+    }
+}
@@ -0,0 +1,55 @@
+use std::{env, str::FromStr};
+
+use crate::errors::AppError;
+
+/// What happens to events routed to a disabled application.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DisabledAppPolicy {
+    /// Discard the events.
+    Drop,
+    /// Hold the events in `parked_event` until the application is resumed.
+    Park,
+    /// Publish the events to this topic instead.
+    Fallback(String),
+}
+
+impl DisabledAppPolicy {
+    pub fn from_env() -> Self {
+        let policy = env::var("DISABLED_APP_POLICY").unwrap_or_else(|_| "drop".to_string());
+        policy.parse().expect("env::DISABLED_APP_POLICY is invalid")
+    }
+}
+
+impl FromStr for DisabledAppPolicy {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(DisabledAppPolicy::Drop),
+            "park" => Ok(DisabledAppPolicy::Park),
+            "fallback" => env::var("DISABLED_APP_FALLBACK_TOPIC")
+                .map(DisabledAppPolicy::Fallback)
+                .map_err(|_| AppError::InternalServerError("DISABLED_APP_FALLBACK_TOPIC is missing".to_string())),
+            other => Err(AppError::InternalServerError(format!("unknown disabled app policy: {other}"))),
+        }
+    }
+}
+
+/// How webhook entries are turned into published payloads.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ForwardMode {
+    /// Parse into the typed model; enables dedupe, filters and subscriptions.
+    Typed,
+    /// Publish each entry's original bytes untouched.
+    Raw,
+}
+
+impl ForwardMode {
+    pub fn from_env() -> Self {
+        match env::var("WEBHOOK_FORWARD_MODE").as_deref() {
+            Ok("raw") => ForwardMode::Raw,
+            Ok("typed") | Err(_) => ForwardMode::Typed,
+            Ok(other) => panic!("env::WEBHOOK_FORWARD_MODE is invalid: {other}"),
+        }
+    }
+}
@@ -0,0 +1,448 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use emit::{__emit_get_event_data, emit, error, info, warn};
+use futures::StreamExt;
+use moka::future::Cache;
+use reqwest::{Client, StatusCode};
+use tokio::task::JoinHandle;
+use validator::Validate;
+
+use crate::{
+    cache::CacheService,
+    database::Database,
+    errors::{AppError, UpstreamError},
+    handlers::auth::{scopes, Principal},
+    models::{
+        merchant_channel::ChannelType,
+        merchant_config::MerchantLookup,
+        send::{GraphErrorResponse, SendMessageRequest, SendMessageResponse},
+    },
+    utils::{config::env_or, token_cipher::TokenCipher},
+};
+
+#[derive(Clone, Debug)]
+pub struct SendConfig {
+    pub base_url: String,
+    pub api_version: String,
+    pub timeout: Duration,
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    pub rate_limit: f64,
+    pub reply_channel: String,
+}
+
+impl SendConfig {
+    pub fn from_env() -> Self {
+        SendConfig {
+            base_url: env_or("GRAPH_API_BASE_URL", "https://graph.facebook.com".to_string()),
+            api_version: env_or("GRAPH_API_VERSION", "v21.0".to_string()),
+            timeout: Duration::from_millis(env_or("SEND_TIMEOUT_MS", 10_000)),
+            max_attempts: env_or("SEND_MAX_ATTEMPTS", 3),
+            base_backoff: Duration::from_millis(env_or("SEND_BASE_BACKOFF_MS", 500)),
+            max_backoff: Duration::from_millis(env_or("SEND_MAX_BACKOFF_MS", 10_000)),
+            rate_limit: env_or("SEND_RATE_LIMIT_PER_SEC", 20.0),
+            reply_channel: env_or("SEND_REPLY_CHANNEL", String::new()),
+        }
+    }
+}
+
+/// Refills at `rate` tokens per second up to a burst of `rate`.
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn take(&mut self, rate: f64) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(rate);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Calls the Graph API Send endpoint with the page access token stored on the
+/// merchant channel, so applications never hold page tokens themselves.
+/// Messages come from `POST /send` or from the Redis Pub/Sub reply channel.
+#[derive(Clone)]
+pub struct Sender {
+    database: Database,
+    cache: CacheService,
+    cipher: TokenCipher,
+    api: SendApi,
+}
+
+impl Sender {
+    pub fn new(database: Database, cache: CacheService, cipher: TokenCipher, config: SendConfig) -> Self {
+        Sender {
+            database,
+            cache,
+            cipher,
+            api: SendApi::new(config),
+        }
+    }
+
+    /// Lets `principal` send as `page_id` only when its application is
+    /// registered to the page; `admin` may send as any page.
+    pub async fn authorize(&self, principal: &Principal, page_id: &str) -> Result<(), AppError> {
+        if principal.scopes.iter().any(|scope| scope == scopes::ADMIN) {
+            return Ok(());
+        }
+
+        match &principal.app_id {
+            Some(app_id) => self.authorize_app(app_id, page_id).await,
+            None => {
+                info!("Principal {} acts for no application, refusing send for page {}", subject: principal.subject, page_id: page_id);
+                Err(AppError::forbidden())
+            }
+        }
+    }
+
+    /// Lets `app_id` send as `page_id` only when it is registered to the page.
+    async fn authorize_app(&self, app_id: &str, page_id: &str) -> Result<(), AppError> {
+        if !self.database.is_registered(ChannelType::Page, page_id, app_id).await? {
+            info!("Application {} is not registered to page {}, refusing send", app_id: app_id, page_id: page_id);
+            return Err(AppError::forbidden());
+        }
+
+        Ok(())
+    }
+
+    /// Sends `request` with the page's access token, see
+    /// [`SendApi::deliver`]. `actor` is recorded in the audit log when the
+    /// page token is decrypted.
+    pub async fn send(&self, request: &SendMessageRequest, actor: &str) -> Result<SendMessageResponse, AppError> {
+        if !request.has_content() {
+            return Err(AppError::bad_request());
+        }
+        let token = self.page_token(&request.page_id, actor).await?;
+        self.api.deliver(&token, request).await
+    }
+
+    /// The decrypted access token of a page with at least one registered
+    /// application.
+    async fn page_token(&self, page_id: &str, actor: &str) -> Result<String, AppError> {
+        let token = match self.database.lookup_merchant(ChannelType::Page, page_id).await? {
+            MerchantLookup::Configured(app_configs) => app_configs.into_iter().next().map(|config| config.token),
+            _ => None,
+        };
+
+        match token.map(|token| self.cipher.decrypt(page_id, &token, actor)).transpose()? {
+            Some(token) if !token.is_empty() => Ok(token),
+            _ => {
+                info!("No page access token for page ID {}", page_id: page_id);
+                Err(AppError::not_found())
+            }
+        }
+    }
+
+    /// Consumes [`SendMessageRequest`]s published on `SEND_REPLY_CHANNEL`.
+    /// Each one names its `app_id` and is only sent when that application is
+    /// registered to the page. Does nothing when the channel is not
+    /// configured.
+    pub fn spawn_listener(&self) -> Option<JoinHandle<()>> {
+        if self.api.config.reply_channel.is_empty() {
+            return None;
+        }
+
+        let sender = self.clone();
+        Some(tokio::spawn(async move { sender.run().await }))
+    }
+
+    async fn run(&self) {
+        loop {
+            if let Err(err) = self.listen().await {
+                warn!("Send reply listener disconnected, error: {}", error: err.to_string());
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    async fn listen(&self) -> Result<(), AppError> {
+        let mut pubsub = self.cache.subscribe(&self.api.config.reply_channel).await?;
+        info!("Listening for replies on {}", channel: self.api.config.reply_channel);
+
+        let mut messages = pubsub.on_message();
+        while let Some(msg) = messages.next().await {
+            let payload: String = msg.get_payload()?;
+            let request = match serde_json::from_str::<SendMessageRequest>(&payload) {
+                Ok(request) => request,
+                Err(err) => {
+                    warn!("Ignoring invalid reply message, error: {}", error: err.to_string());
+                    continue;
+                }
+            };
+            if let Err(err) = request.validate() {
+                warn!("Ignoring invalid reply message, error: {}", error: err.to_string());
+                continue;
+            }
+            let Some(app_id) = request.app_id.clone() else {
+                warn!("Ignoring reply for page {} without app_id", page_id: request.page_id);
+                continue;
+            };
+
+            // one slow page must not hold up the others
+            let sender = self.clone();
+            tokio::spawn(async move {
+                let trace_id = request.trace_id.clone().unwrap_or_default();
+                let result = match sender.authorize_app(&app_id, &request.page_id).await {
+                    Ok(()) => sender.send(&request, &format!("reply-channel:{app_id}")).await,
+                    Err(err) => Err(err),
+                };
+                match result {
+                    Ok(response) => info!("Reply sent for page {}, message ID {}, trace ID {}",
+                        page_id: request.page_id,
+                        message_id: response.message_id.unwrap_or_default(),
+                        trace_id: trace_id),
+                    Err(err) => error!("Reply for page {} dropped, trace ID {}, error: {}",
+                        page_id: request.page_id,
+                        trace_id: trace_id,
+                        error: err.to_string()),
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// The HTTP side of [`Sender`]: the per-page rate limit, the Send API call
+/// and its retries.
+#[derive(Clone)]
+struct SendApi {
+    http: Client,
+    config: SendConfig,
+    buckets: Cache<String, Arc<Mutex<TokenBucket>>>,
+}
+
+impl SendApi {
+    fn new(config: SendConfig) -> Self {
+        SendApi {
+            http: Client::builder()
+                .connect_timeout(config.timeout)
+                .build()
+                .unwrap_or_default(),
+            config,
+            buckets: Cache::builder()
+                .time_to_idle(Duration::from_secs(600))
+                .build(),
+        }
+    }
+
+    /// Sends `request`, retrying with exponential backoff up to
+    /// `max_attempts` times while Graph cannot have handled it: refused
+    /// connections and rate limits.
+    async fn deliver(&self, token: &str, request: &SendMessageRequest) -> Result<SendMessageResponse, AppError> {
+
+        let mut attempt = 1;
+        loop {
+            let result = match self.acquire(&request.page_id).await {
+                Ok(()) => self.post(&request.page_id, token, request).await,
+                Err(err) => Err(err.into()),
+            };
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(AppError::Upstream(err)) if err.is_retryable() && attempt < self.config.max_attempts => {
+                    let backoff = self.backoff(attempt);
+                    info!("Send for page {} failed, attempt {}, retrying in ms {}, error: {}",
+                        page_id: request.page_id,
+                        attempt: attempt,
+                        retry_in_ms: backoff.as_millis() as u64,
+                        error: err.to_string());
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                Err(err) => {
+                    warn!("Send for page {} failed, attempt {}, error: {}",
+                        page_id: request.page_id,
+                        attempt: attempt,
+                        error: err.to_string());
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    async fn acquire(&self, page_id: &str) -> Result<(), UpstreamError> {
+        let rate = self.config.rate_limit;
+        let bucket = self
+            .buckets
+            .get_with(page_id.to_string(), async move {
+                Arc::new(Mutex::new(TokenBucket {
+                    tokens: rate,
+                    updated: Instant::now(),
+                }))
+            })
+            .await;
+
+        let taken = match bucket.lock() {
+            Ok(mut bucket) => bucket.take(rate),
+            // a panic mid-update leaves the bucket usable
+            Err(poisoned) => poisoned.into_inner().take(rate),
+        };
+        if taken {
+            Ok(())
+        } else {
+            Err(UpstreamError::RateLimited(format!("local limit of {rate}/s for page {page_id}")))
+        }
+    }
+
+    /// Calls the Send API once. The Send API is not idempotent, so once the
+    /// request may have gone out a failure is a [`AppError::Timeout`] and is
+    /// not retried; only a refused connection is [`UpstreamError::Unavailable`].
+    async fn post(&self, page_id: &str, token: &str, request: &SendMessageRequest) -> Result<SendMessageResponse, AppError> {
+        let url = format!("{}/{}/{}/messages", self.config.base_url, self.config.api_version, page_id);
+        let response = match self
+            .http
+            .post(url)
+            .bearer_auth(token)
+            .timeout(self.config.timeout)
+            .json(request)
+            .send()
+            .await
+        {
+            Ok(response) => response,
+            Err(err) if err.is_connect() => return Err(UpstreamError::Unavailable(err.to_string()).into()),
+            Err(err) => {
+                return Err(AppError::Timeout(format!("send for page {page_id} may have been delivered: {err}")));
+            }
+        };
+
+        let status = response.status();
+        let body = response.bytes().await;
+
+        if status.is_success() {
+            // Graph accepted the message, an unreadable answer does not undo that
+            let response = body
+                .map_err(|err| err.to_string())
+                .and_then(|body| serde_json::from_slice::<SendMessageResponse>(&body).map_err(|err| err.to_string()));
+            return Ok(response.unwrap_or_else(|err| {
+                warn!("Unreadable Send API response for page {}, error: {}", page_id: page_id, error: err);
+                SendMessageResponse::default()
+            }));
+        }
+
+        let body = body.unwrap_or_default();
+        Err(match serde_json::from_slice::<GraphErrorResponse>(&body) {
+            Ok(GraphErrorResponse { error }) => {
+                let message = format!("{} (code {}, subcode {}, fbtrace_id {})",
+                    error.message,
+                    error.code.unwrap_or_default(),
+                    error.error_subcode.unwrap_or_default(),
+                    error.fbtrace_id.unwrap_or_default());
+                classify(page_id, status, error.code, message)
+            }
+            Err(_) => classify(page_id, status, None, format!("status {status}")),
+        })
+    }
+
+    /// Exponential backoff: `base * 2^(attempt - 1)`, capped at `max_backoff`.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        self.config
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.config.max_backoff)
+    }
+}
+
+/// Maps a failed Graph response onto [`AppError`], see
+/// <https://developers.facebook.com/docs/graph-api/guides/error-handling>.
+/// A server error means the request reached Graph and may have been handled,
+/// so it is a [`AppError::Timeout`] rather than a retryable error.
+fn classify(page_id: &str, status: StatusCode, code: Option<i64>, message: String) -> AppError {
+    match code {
+        Some(4 | 17 | 32 | 613 | 80001..=80014) => UpstreamError::RateLimited(message).into(),
+        Some(102 | 190 | 200..=299) => UpstreamError::Unauthorized(message).into(),
+        _ if status == StatusCode::TOO_MANY_REQUESTS => UpstreamError::RateLimited(message).into(),
+        _ if status.is_server_error() => {
+            AppError::Timeout(format!("send for page {page_id} may have been delivered: {message}"))
+        }
+        _ if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN => {
+            UpstreamError::Unauthorized(message).into()
+        }
+        _ => UpstreamError::Rejected(message).into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::{http::StatusCode as AxumStatus, routing::post, Json, Router};
+    use serde_json::json;
+
+    use super::*;
+
+    /// A Send API answering every call with `status` and `body`, and the
+    /// number of calls it received.
+    async fn graph(status: u16, body: serde_json::Value) -> (SendApi, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/v21.0/:page_id/messages",
+            post(move || {
+                let counter = counter.clone();
+                let body = body.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    (AxumStatus::from_u16(status).unwrap(), Json(body))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let api = SendApi::new(SendConfig {
+            base_url: format!("http://{address}"),
+            api_version: "v21.0".to_string(),
+            timeout: Duration::from_secs(5),
+            max_attempts: 3,
+            base_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+            rate_limit: 100.0,
+            reply_channel: String::new(),
+        });
+        (api, calls)
+    }
+
+    fn request() -> SendMessageRequest {
+        serde_json::from_value(json!({
+            "page_id": "123",
+            "recipient": {"id": "456"},
+            "message": {"text": "hello"},
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_retried() {
+        let (api, calls) = graph(500, json!({"error": {"message": "An unknown error occurred", "code": 1}})).await;
+
+        let result = api.deliver("token", &request()).await;
+        assert!(matches!(result, Err(AppError::Timeout(_))), "{result:?}");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_retried() {
+        let (api, calls) = graph(400, json!({"error": {"message": "Too many calls", "code": 4}})).await;
+
+        let result = api.deliver("token", &request()).await;
+        assert!(matches!(result, Err(AppError::Upstream(UpstreamError::RateLimited(_)))), "{result:?}");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,104 @@
+use std::env;
+
+use async_trait::async_trait;
+use lapin::{
+    options::{BasicPublishOptions, ConfirmSelectOptions, ExchangeDeclareOptions},
+    types::FieldTable,
+    BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
+};
+use tokio::sync::Mutex;
+
+use crate::{errors::AppError, sinks::MessageSink};
+
+const DEFAULT_EXCHANGE: &str = "femto.webhooks";
+const PERSISTENT_DELIVERY: u8 = 2;
+
+/// Publishes to a durable topic exchange with the app topic as routing key and
+/// waits for the broker's publisher confirm. Messages are published
+/// `mandatory`, so one that no queue is bound for comes back as a failure
+/// instead of being silently dropped by the broker.
+pub struct AmqpSink {
+    uri: String,
+    exchange: String,
+    channel: Mutex<Option<(Connection, Channel)>>,
+}
+
+impl AmqpSink {
+    pub fn new(uri: String) -> Self {
+        let exchange = env::var("AMQP_EXCHANGE").unwrap_or(DEFAULT_EXCHANGE.to_string());
+
+        AmqpSink {
+            uri,
+            exchange,
+            channel: Mutex::new(None),
+        }
+    }
+
+    /// Returns the open channel, reconnecting if the broker dropped it.
+    async fn channel(&self) -> Result<Channel, AppError> {
+        let mut guard = self.channel.lock().await;
+        if let Some((connection, channel)) = guard.as_ref() {
+            if connection.status().connected() && channel.status().connected() {
+                return Ok(channel.clone());
+            }
+        }
+
+        let connection = Connection::connect(&self.uri, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
+        channel
+            .exchange_declare(
+                &self.exchange,
+                ExchangeKind::Topic,
+                ExchangeDeclareOptions {
+                    durable: true,
+                    ..ExchangeDeclareOptions::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        *guard = Some((connection, channel.clone()));
+        Ok(channel)
+    }
+}
+
+#[async_trait]
+impl MessageSink for AmqpSink {
+    async fn publish(&self, topic: &str, payload: &str) -> Result<(), AppError> {
+        let channel = self.channel().await?;
+        let properties = BasicProperties::default()
+            .with_delivery_mode(PERSISTENT_DELIVERY)
+            .with_content_type(mime::APPLICATION_JSON.as_ref().into());
+
+        let confirmation = channel
+            .basic_publish(
+                &self.exchange,
+                topic,
+                BasicPublishOptions {
+                    mandatory: true,
+                    ..BasicPublishOptions::default()
+                },
+                payload.as_bytes(),
+                properties,
+            )
+            .await?
+            .await?;
+
+        if confirmation.is_nack() {
+            return Err(AppError::InternalServerError(format!(
+                "amqp broker rejected message for {topic}"
+            )));
+        }
+        if let Some(returned) = confirmation.take_message() {
+            return Err(AppError::InternalServerError(format!(
+                "amqp broker returned unroutable message for {topic}: {}",
+                returned.reply_text
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+use std::{env, fmt, str::FromStr, sync::Arc};
+
+use async_trait::async_trait;
+use emit::{__emit_get_event_data, emit, info};
+use serde::{Deserialize, Serialize};
+
+use crate::{cache::CacheService, errors::AppError};
+
+pub mod amqp;
+pub mod redis_pubsub;
+pub mod redis_stream;
+
+/// Delivery backend for messages the gateway forwards to downstream apps.
+#[async_trait]
+pub trait MessageSink: Send + Sync {
+    async fn publish(&self, topic: &str, payload: &str) -> Result<(), AppError>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    RedisPubsub,
+    RedisStream,
+    Amqp,
+}
+
+impl SinkKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SinkKind::RedisPubsub => "redis_pubsub",
+            SinkKind::RedisStream => "redis_stream",
+            SinkKind::Amqp => "amqp",
+        }
+    }
+}
+
+impl fmt::Display for SinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for SinkKind {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "redis_pubsub" => Ok(SinkKind::RedisPubsub),
+            "redis_stream" => Ok(SinkKind::RedisStream),
+            "amqp" => Ok(SinkKind::Amqp),
+            other => Err(AppError::InternalServerError(format!(
+                "unknown sink type: {other}"
+            ))),
+        }
+    }
+}
+
+/// All configured sinks. An application row selects one of them through its
+/// `sink_type` column.
+#[derive(Clone)]
+pub struct MessageSinks {
+    redis_pubsub: Arc<dyn MessageSink>,
+    redis_stream: Arc<dyn MessageSink>,
+    amqp: Option<Arc<dyn MessageSink>>,
+}
+
+impl MessageSinks {
+    pub async fn init(cache: &CacheService) -> Self {
+        let amqp: Option<Arc<dyn MessageSink>> = match env::var("AMQP_URL") {
+            Ok(uri) => Some(Arc::new(amqp::AmqpSink::new(uri))),
+            Err(_) => {
+                info!("AMQP_URL is not set, {} sink is disabled", sink: SinkKind::Amqp.as_str());
+                None
+            }
+        };
+
+        MessageSinks {
+            redis_pubsub: Arc::new(redis_pubsub::RedisPubSubSink::new(cache.clone())),
+            redis_stream: Arc::new(redis_stream::RedisStreamSink::new(cache.clone())),
+            amqp,
+        }
+    }
+
+    pub fn get(&self, kind: SinkKind) -> Result<Arc<dyn MessageSink>, AppError> {
+        match kind {
+            SinkKind::RedisPubsub => Ok(self.redis_pubsub.clone()),
+            SinkKind::RedisStream => Ok(self.redis_stream.clone()),
+            SinkKind::Amqp => self.amqp.clone().ok_or_else(|| {
+                AppError::InternalServerError("amqp sink is not configured".to_string())
+            }),
+        }
+    }
+
+    pub async fn publish(&self, kind: SinkKind, topic: &str, payload: &str) -> Result<(), AppError> {
+        self.get(kind)?.publish(topic, payload).await
+    }
+}
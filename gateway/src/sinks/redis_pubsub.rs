@@ -0,0 +1,22 @@
+use async_trait::async_trait;
+
+use crate::{cache::CacheService, errors::AppError, sinks::MessageSink};
+
+/// Fire-and-forget Redis `PUBLISH`. Messages are lost when no subscriber is
+/// connected.
+pub struct RedisPubSubSink {
+    cache: CacheService,
+}
+
+impl RedisPubSubSink {
+    pub fn new(cache: CacheService) -> Self {
+        RedisPubSubSink { cache }
+    }
+}
+
+#[async_trait]
+impl MessageSink for RedisPubSubSink {
+    async fn publish(&self, topic: &str, payload: &str) -> Result<(), AppError> {
+        self.cache.publish(topic.to_string(), payload.to_string()).await
+    }
+}
@@ -0,0 +1,78 @@
+use async_trait::async_trait;
+use moka::future::Cache;
+use redis::aio::ConnectionManager;
+
+use crate::{cache::CacheService, errors::AppError, sinks::MessageSink, utils::config::env_or};
+
+const DEFAULT_MAX_LEN: usize = 100_000;
+const DEFAULT_GROUP: &str = "femto";
+
+/// Appends messages to a Redis stream per topic with `XADD ... MAXLEN ~`, so
+/// consumers that are offline can catch up through their consumer group.
+pub struct RedisStreamSink {
+    cache: CacheService,
+    max_len: usize,
+    group: String,
+    groups: Cache<String, ()>,
+}
+
+impl RedisStreamSink {
+    pub fn new(cache: CacheService) -> Self {
+        let max_len = env_or("REDIS_STREAM_MAXLEN", DEFAULT_MAX_LEN);
+        let group = env_or("REDIS_STREAM_GROUP", DEFAULT_GROUP.to_string());
+
+        RedisStreamSink {
+            cache,
+            max_len,
+            group,
+            groups: Cache::builder().max_capacity(10_000).build(),
+        }
+    }
+
+    /// Creates the consumer group for `topic` once per process. The group starts
+    /// at the end of the stream so it receives everything published after it.
+    async fn ensure_group(&self, conn: &mut ConnectionManager, topic: &str) -> Result<(), AppError> {
+        if self.groups.contains_key(topic) {
+            return Ok(());
+        }
+
+        let created: redis::RedisResult<()> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(topic)
+            .arg(&self.group)
+            .arg("$")
+            .arg("MKSTREAM")
+            .query_async(conn)
+            .await;
+
+        match created {
+            Ok(()) => {}
+            Err(err) if err.code() == Some("BUSYGROUP") => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        self.groups.insert(topic.to_string(), ()).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MessageSink for RedisStreamSink {
+    async fn publish(&self, topic: &str, payload: &str) -> Result<(), AppError> {
+        let mut conn = self.cache.connection().await?;
+        self.ensure_group(&mut conn, topic).await?;
+
+        let _: String = redis::cmd("XADD")
+            .arg(topic)
+            .arg("MAXLEN")
+            .arg("~")
+            .arg(self.max_len)
+            .arg("*")
+            .arg("payload")
+            .arg(payload)
+            .query_async(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+use std::{env, str::FromStr};
+
+/// Reads and parses an environment variable, falling back to `default` when it
+/// is unset or cannot be parsed.
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Reads and parses an environment variable, falling back to `default` only
+/// when it is unset. A value that does not parse fails startup.
+pub fn env_parse<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("env::{name} is invalid: {value}")),
+        Err(_) => default,
+    }
+}
@@ -62,10 +62,10 @@ where
         self
     }
 
-    // pub fn pagination(mut self, pagination: ResponsePagination) -> Self {
-    //     self.pagination = Some(pagination);
-    //     self
-    // }
+    pub fn pagination(mut self, pagination: ResponsePagination) -> Self {
+        self.pagination = Some(pagination);
+        self
+    }
 
     pub fn build(self) -> CustomResponse<T> {
         CustomResponse {
@@ -8,7 +8,7 @@ use std::fmt::Write;
 
 pub const DEFAULT_EVENT_BODY_LIMIT_BYTES: usize = 1024 * 256;
 pub const DEFAULT_BATCH_LIMIT_BYTES: usize = 1024 * 1024 * 10;
-pub const LOCAL_SERVER_URL: &'static str = "http://localhost:5341/";
+pub const LOCAL_SERVER_URL: &str = "http://localhost:5341/";
 
 #[derive(Debug)]
 pub struct SeqCollectorBuilder {
@@ -62,7 +62,7 @@ impl SeqCollectorBuilder {
 
 // 0 is "OFF", but fatal is the best effort for rendering this if we ever get an
 // event with that level.
-static SEQ_LEVEL_NAMES: [&'static str; 6] = [
+static SEQ_LEVEL_NAMES: [&str; 6] = [
     "Fatal",
     "Error",
     "Warning",
@@ -97,7 +97,7 @@ impl SeqCollector {
     fn send_batch(&self, payload: &String) -> Result<(), Box<dyn Error>> {
         log::debug!("logging {}", payload);
         let client = reqwest::blocking::Client::new();
-        if !self.api_key.is_none() {
+        if self.api_key.is_some() {
             let api_key = self.api_key.clone().unwrap();
             let _res = client
                 .post(&self.endpoint)
@@ -112,9 +112,9 @@ impl SeqCollector {
     }
 }
 
-const HEADER: &'static str = "{\"Events\":[";
+const HEADER: &str = "{\"Events\":[";
 const HEADER_LEN: usize = 11;
-const FOOTER: &'static str = "]}";
+const FOOTER: &str = "]}";
 const FOOTER_LEN: usize = 2;
 
 impl emit::collectors::AcceptEvents for SeqCollector {
@@ -135,7 +135,7 @@ impl emit::collectors::AcceptEvents for SeqCollector {
             }
 
             // Make sure at least one event is included in each batch
-            if delim != "" && count + delim.len() + payload.len() > self.batch_limit_bytes {
+            if !delim.is_empty() && count + delim.len() + payload.len() > self.batch_limit_bytes {
                 write!(next, "{}", FOOTER).unwrap();
                 let _ = self.send_batch(&next);
 
@@ -167,7 +167,7 @@ fn format_payload(event: &Event) -> String {
     let mut first = true;
     for (n, v) in event.properties() {
         if !first {
-            body.push_str(",");
+            body.push(',');
         } else {
             first = false;
         }
@@ -186,7 +186,6 @@ fn format_oversize_placeholder(event: &Event) -> String {
             .text()
             .chars()
             .take(64)
-            .into_iter()
             .collect()
     } else {
         event.message_template().text().clone()
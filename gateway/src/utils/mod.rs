@@ -1,2 +1,5 @@
+pub mod config;
 pub mod custom_response;
-pub mod emit_seq;
\ No newline at end of file
+pub mod emit_seq;
+pub mod signature;
+pub mod token_cipher;
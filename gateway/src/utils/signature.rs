@@ -0,0 +1,77 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SIGNATURE_HEADER: &str = "x-hub-signature-256";
+const SIGNATURE_PREFIX: &str = "sha256=";
+
+/// Extracts the raw HMAC bytes from an `X-Hub-Signature-256` header value
+/// (`sha256=<hex digest>`). Returns `None` when the header is malformed.
+pub fn parse_signature(header: &str) -> Option<Vec<u8>> {
+    let digest = header.trim().strip_prefix(SIGNATURE_PREFIX)?;
+    hex::decode(digest).ok().filter(|signature| !signature.is_empty())
+}
+
+/// Verifies `signature` against the HMAC-SHA256 of `payload` keyed with `secret`.
+/// The comparison is constant-time.
+pub fn verify_signature(secret: &str, payload: &[u8], signature: &[u8]) -> bool {
+    let mut mac = match HmacSha256::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(payload);
+    mac.verify_slice(signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "app-secret";
+    const PAYLOAD: &[u8] = br#"{"object":"page","entry":[]}"#;
+
+    fn sign(secret: &str, payload: &[u8]) -> String {
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(payload);
+        format!("{SIGNATURE_PREFIX}{}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    #[test]
+    fn parse_signature_accepts_prefixed_hex() {
+        let header = sign(SECRET, PAYLOAD);
+        let signature = parse_signature(&header).unwrap();
+        assert_eq!(signature.len(), 32);
+        assert_eq!(parse_signature(&format!("  {header} ")), Some(signature));
+    }
+
+    #[test]
+    fn parse_signature_rejects_malformed_headers() {
+        assert_eq!(parse_signature(""), None);
+        assert_eq!(parse_signature("sha256="), None);
+        assert_eq!(parse_signature("sha1=abcdef"), None);
+        assert_eq!(parse_signature("abcdef"), None);
+        assert_eq!(parse_signature("sha256=not-hex"), None);
+        assert_eq!(parse_signature("sha256=abc"), None);
+    }
+
+    #[test]
+    fn verify_signature_accepts_valid_signature() {
+        let signature = parse_signature(&sign(SECRET, PAYLOAD)).unwrap();
+        assert!(verify_signature(SECRET, PAYLOAD, &signature));
+    }
+
+    #[test]
+    fn verify_signature_rejects_wrong_secret_or_payload() {
+        let signature = parse_signature(&sign(SECRET, PAYLOAD)).unwrap();
+        assert!(!verify_signature("other-secret", PAYLOAD, &signature));
+        assert!(!verify_signature(SECRET, br#"{"object":"page","entry":[{}]}"#, &signature));
+    }
+
+    #[test]
+    fn verify_signature_rejects_missing_or_truncated_signature() {
+        let signature = parse_signature(&sign(SECRET, PAYLOAD)).unwrap();
+        assert!(!verify_signature(SECRET, PAYLOAD, &[]));
+        assert!(!verify_signature(SECRET, PAYLOAD, &signature[..16]));
+    }
+}
@@ -0,0 +1,289 @@
+use std::{collections::HashMap, sync::Arc};
+
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use emit::{__emit_get_event_data, emit, info, warn};
+
+use crate::{errors::AppError, models::merchant_channel::EncryptedToken, utils::config::env_or};
+
+const NONCE_LEN: usize = 12;
+/// Marks tokens sealed with the channel `ref_id` as associated data. Tokens
+/// written before carry no prefix and are opened without it until rotated.
+const BOUND_PREFIX: &str = "v2:";
+
+/// Envelope encryption of page access tokens. Each token is sealed with its
+/// own random data key, and the data key is sealed with the master key named
+/// by `token_key_id`, so rotating the master key only re-wraps data keys.
+/// Both are bound to the channel's `ref_id`, so a ciphertext copied onto
+/// another channel row does not decrypt.
+///
+/// Master keys come from `TOKEN_ENCRYPTION_KEYS` as `id:base64key` pairs
+/// separated by commas; `TOKEN_ENCRYPTION_KEY_ID` picks the one new tokens are
+/// written with. Storing tokens as plaintext needs `TOKEN_ALLOW_PLAINTEXT=true`.
+#[derive(Clone)]
+pub struct TokenCipher {
+    keys: Arc<HashMap<String, Key<Aes256Gcm>>>,
+    active: Option<String>,
+}
+
+impl TokenCipher {
+    /// Fails on any unusable key, an unknown active key id, or missing keys
+    /// without the plaintext opt-out, so a misconfigured instance never
+    /// writes tokens it cannot read back.
+    pub fn from_env() -> Result<Self, AppError> {
+        Self::from_config(
+            &env_or("TOKEN_ENCRYPTION_KEYS", String::new()),
+            &env_or("TOKEN_ENCRYPTION_KEY_ID", String::new()),
+            env_or("TOKEN_ALLOW_PLAINTEXT", false),
+        )
+    }
+
+    fn from_config(keys: &str, active_id: &str, allow_plaintext: bool) -> Result<Self, AppError> {
+        let invalid = |message: String| AppError::InternalServerError(message);
+
+        let mut parsed = HashMap::new();
+        let mut first = None;
+        for pair in keys.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let (id, key) = pair
+                .split_once(':')
+                .ok_or_else(|| invalid("env::TOKEN_ENCRYPTION_KEYS expects key_id:base64 pairs".to_string()))?;
+            let bytes = STANDARD
+                .decode(key.trim())
+                .ok()
+                .filter(|bytes| bytes.len() == 32)
+                .ok_or_else(|| invalid(format!("token encryption key {id} is not 32 base64 encoded bytes")))?;
+            first.get_or_insert_with(|| id.to_string());
+            parsed.insert(id.to_string(), *Key::<Aes256Gcm>::from_slice(&bytes));
+        }
+
+        let active = match Some(active_id).filter(|id| !id.is_empty()) {
+            Some(id) if parsed.contains_key(id) => Some(id.to_string()),
+            Some(id) => return Err(invalid(format!("token encryption key {id} is not configured"))),
+            None => first,
+        };
+        if active.is_none() {
+            if !allow_plaintext {
+                return Err(invalid(
+                    "env::TOKEN_ENCRYPTION_KEYS is missing, set TOKEN_ALLOW_PLAINTEXT=true to store page tokens unencrypted"
+                        .to_string(),
+                ));
+            }
+            warn!("No token encryption key configured, page access tokens are stored as plaintext",);
+        }
+
+        Ok(TokenCipher {
+            keys: Arc::new(parsed),
+            active,
+        })
+    }
+
+    /// Encrypts the token of the channel `ref_id`.
+    pub fn encrypt(&self, ref_id: &str, plaintext: &str) -> Result<EncryptedToken, AppError> {
+        let Some((key_id, key)) = self.active_key() else {
+            return Ok(EncryptedToken::plaintext(plaintext));
+        };
+
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        Ok(EncryptedToken {
+            token: format!("{BOUND_PREFIX}{}", seal(&data_key, plaintext.as_bytes(), ref_id)?),
+            token_key_id: Some(key_id.to_string()),
+            token_dek: Some(seal(key, &data_key, ref_id)?),
+        })
+    }
+
+    /// Decrypts the token of the channel `ref_id` for `actor` and records
+    /// that in the audit log. Only call this where the token is about to be
+    /// used or returned.
+    pub fn decrypt(&self, ref_id: &str, token: &EncryptedToken, actor: &str) -> Result<String, AppError> {
+        let plaintext = self.open_token(ref_id, token)?;
+        info!("Audit: token of merchant channel {} decrypted for {}", audit_ref_id: ref_id, audit_actor: actor);
+        Ok(plaintext)
+    }
+
+    /// Re-wraps the data key of `token` with the active key, encrypting it
+    /// first if it is plaintext and re-sealing it if it predates `ref_id`
+    /// binding. `None` when it is already current.
+    pub fn rotate(&self, ref_id: &str, token: &EncryptedToken) -> Result<Option<EncryptedToken>, AppError> {
+        let Some((key_id, key)) = self.active_key() else {
+            return Ok(None);
+        };
+        let bound = token.token.starts_with(BOUND_PREFIX);
+        if bound && token.token_key_id.as_deref() == Some(key_id) {
+            return Ok(None);
+        }
+
+        match self.unwrap_data_key(ref_id, token)? {
+            Some(data_key) if bound => Ok(Some(EncryptedToken {
+                token: token.token.clone(),
+                token_key_id: Some(key_id.to_string()),
+                token_dek: Some(seal(key, &data_key, ref_id)?),
+            })),
+            _ => self.encrypt(ref_id, &self.open_token(ref_id, token)?).map(Some),
+        }
+    }
+
+    fn active_key(&self) -> Option<(&str, &Key<Aes256Gcm>)> {
+        let id = self.active.as_deref()?;
+        self.keys.get(id).map(|key| (id, key))
+    }
+
+    fn open_token(&self, ref_id: &str, token: &EncryptedToken) -> Result<String, AppError> {
+        let Some(data_key) = self.unwrap_data_key(ref_id, token)? else {
+            return Ok(token.token.clone());
+        };
+
+        let data_key = Key::<Aes256Gcm>::from_slice(&data_key);
+        let plaintext = match token.token.strip_prefix(BOUND_PREFIX) {
+            Some(sealed) => open(data_key, sealed, ref_id)?,
+            None => open(data_key, &token.token, "")?,
+        };
+        String::from_utf8(plaintext).map_err(|err| AppError::InternalServerError(err.to_string()))
+    }
+
+    /// The data key of `token`, or `None` for a plaintext token.
+    fn unwrap_data_key(&self, ref_id: &str, token: &EncryptedToken) -> Result<Option<Vec<u8>>, AppError> {
+        let (Some(key_id), Some(data_key)) = (&token.token_key_id, &token.token_dek) else {
+            return Ok(None);
+        };
+        let key = self.keys.get(key_id).ok_or_else(|| {
+            AppError::InternalServerError(format!("token encryption key {key_id} is not configured"))
+        })?;
+
+        let aad = if token.token.starts_with(BOUND_PREFIX) { ref_id } else { "" };
+        open(key, data_key, aad).map(Some)
+    }
+}
+
+/// `base64(nonce || ciphertext)`, authenticated together with `aad`.
+fn seal(key: &Key<Aes256Gcm>, plaintext: &[u8], aad: &str) -> Result<String, AppError> {
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let payload = Payload {
+        msg: plaintext,
+        aad: aad.as_bytes(),
+    };
+    let ciphertext = Aes256Gcm::new(key)
+        .encrypt(&nonce, payload)
+        .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+
+    let mut sealed = nonce.to_vec();
+    sealed.extend_from_slice(&ciphertext);
+    Ok(STANDARD.encode(sealed))
+}
+
+fn open(key: &Key<Aes256Gcm>, sealed: &str, aad: &str) -> Result<Vec<u8>, AppError> {
+    let sealed = STANDARD
+        .decode(sealed)
+        .map_err(|err| AppError::InternalServerError(err.to_string()))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(AppError::InternalServerError("sealed token is truncated".to_string()));
+    }
+
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let payload = Payload {
+        msg: ciphertext,
+        aad: aad.as_bytes(),
+    };
+    Aes256Gcm::new(key)
+        .decrypt(Nonce::from_slice(nonce), payload)
+        .map_err(|_| AppError::InternalServerError("token decryption failed".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY_A: &str = "a:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    const KEY_B: &str = "b:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    fn cipher(keys: &str, active_id: &str) -> TokenCipher {
+        TokenCipher::from_config(keys, active_id, false).unwrap()
+    }
+
+    /// A token sealed the way tokens were before `ref_id` binding.
+    fn legacy_token(cipher: &TokenCipher, plaintext: &str) -> EncryptedToken {
+        let (key_id, key) = cipher.active_key().unwrap();
+        let data_key = Aes256Gcm::generate_key(OsRng);
+        EncryptedToken {
+            token: seal(&data_key, plaintext.as_bytes(), "").unwrap(),
+            token_key_id: Some(key_id.to_string()),
+            token_dek: Some(seal(key, &data_key, "").unwrap()),
+        }
+    }
+
+    #[test]
+    fn encrypt_then_decrypt_round_trips() {
+        let cipher = cipher(KEY_A, "");
+        let token = cipher.encrypt("123", "EAAB-page-token").unwrap();
+        assert_ne!(token.token, "EAAB-page-token");
+        assert_eq!(token.token_key_id.as_deref(), Some("a"));
+        assert_eq!(cipher.decrypt("123", &token, "test").unwrap(), "EAAB-page-token");
+    }
+
+    #[test]
+    fn rotate_rewraps_under_new_key() {
+        let old = cipher(KEY_A, "");
+        let token = old.encrypt("123", "EAAB-page-token").unwrap();
+
+        let new = cipher(&format!("{KEY_A},{KEY_B}"), "b");
+        let rotated = new.rotate("123", &token).unwrap().unwrap();
+        assert_eq!(rotated.token_key_id.as_deref(), Some("b"));
+        assert_eq!(rotated.token, token.token);
+        assert_eq!(new.decrypt("123", &rotated, "test").unwrap(), "EAAB-page-token");
+        assert!(new.rotate("123", &rotated).unwrap().is_none());
+
+        let only_new = cipher(KEY_B, "b");
+        assert_eq!(only_new.decrypt("123", &rotated, "test").unwrap(), "EAAB-page-token");
+    }
+
+    #[test]
+    fn rotate_binds_legacy_and_plaintext_tokens() {
+        let cipher = cipher(KEY_A, "");
+        let legacy = legacy_token(&cipher, "EAAB-page-token");
+        assert_eq!(cipher.decrypt("123", &legacy, "test").unwrap(), "EAAB-page-token");
+
+        let rotated = cipher.rotate("123", &legacy).unwrap().unwrap();
+        assert!(rotated.token.starts_with(BOUND_PREFIX));
+        assert_eq!(cipher.decrypt("123", &rotated, "test").unwrap(), "EAAB-page-token");
+
+        let plaintext = EncryptedToken::plaintext("EAAB-page-token");
+        let rotated = cipher.rotate("123", &plaintext).unwrap().unwrap();
+        assert_eq!(cipher.decrypt("123", &rotated, "test").unwrap(), "EAAB-page-token");
+    }
+
+    #[test]
+    fn tampered_ciphertext_fails() {
+        let cipher = cipher(KEY_A, "");
+        let mut token = cipher.encrypt("123", "EAAB-page-token").unwrap();
+        let mut sealed = STANDARD.decode(token.token.strip_prefix(BOUND_PREFIX).unwrap()).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        token.token = format!("{BOUND_PREFIX}{}", STANDARD.encode(sealed));
+        assert!(cipher.decrypt("123", &token, "test").is_err());
+    }
+
+    #[test]
+    fn token_of_another_channel_fails() {
+        let cipher = cipher(KEY_A, "");
+        let token = cipher.encrypt("123", "EAAB-page-token").unwrap();
+        assert!(cipher.decrypt("456", &token, "test").is_err());
+    }
+
+    #[test]
+    fn unknown_key_id_fails() {
+        let token = cipher(KEY_A, "").encrypt("123", "EAAB-page-token").unwrap();
+        assert!(cipher(KEY_B, "").decrypt("123", &token, "test").is_err());
+    }
+
+    #[test]
+    fn invalid_configuration_is_refused() {
+        assert!(TokenCipher::from_config("a:not-base64", "", false).is_err());
+        assert!(TokenCipher::from_config("a:AAAA", "", false).is_err());
+        assert!(TokenCipher::from_config("missing-separator", "", false).is_err());
+        assert!(TokenCipher::from_config(KEY_A, "b", false).is_err());
+        assert!(TokenCipher::from_config("", "", false).is_err());
+        assert!(TokenCipher::from_config("", "", true).unwrap().active.is_none());
+    }
+}